@@ -0,0 +1,115 @@
+//! Heap allocations made by a refresh
+//!
+//! A counting global allocator checks that once the buffers and tables have
+//! grown (warm-up), encoding a frame allocates nothing, then the frames are
+//! timed as usual.
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zaz::__bench::{headless_screen, render_frame};
+use zaz::{Attr, Color, Screen};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ROWS: u16 = 60;
+const COLS: u16 = 200;
+
+/// Log lines in assorted colors, some with a hyperlink
+fn log_lines() -> Vec<(Color, Attr, String)> {
+    (0..500)
+        .map(|i| {
+            let color = match i % 4 {
+                0 => Color::Green,
+                1 => Color::Ansi256((i % 256) as u8),
+                2 => Color::Rgb(i as u8, 128, 255 - i as u8),
+                _ => Color::Reset,
+            };
+            let attr = if i % 7 == 0 { Attr::BOLD } else { Attr::NORMAL };
+            let text = format!(
+                "{:>5} request handled in {}ms by worker {}",
+                i,
+                i * 7 % 300,
+                i % 13
+            );
+            (color, attr, format!("{:<1$}", text, COLS as usize - 20))
+        })
+        .collect()
+}
+
+/// Draw frame `n`: the log scrolled by one line, and a status line
+fn draw(scr: &mut Screen, lines: &[(Color, Attr, String)], n: usize) {
+    for row in 0..ROWS - 1 {
+        let (color, attr, text) = &lines[(n + row as usize) % lines.len()];
+        scr.attrset(*attr).unwrap();
+        scr.set_fg(*color).unwrap();
+        scr.mvprint(row, 0, text).unwrap();
+        scr.set_hyperlink(Some("https://example.com/logs")).unwrap();
+        scr.print("details").unwrap();
+        scr.set_hyperlink(None).unwrap();
+    }
+    scr.attrset(Attr::REVERSE).unwrap();
+    scr.set_fg(Color::Reset).unwrap();
+    scr.mvprint(
+        ROWS - 1,
+        0,
+        if n % 2 == 0 {
+            "status: ok  "
+        } else {
+            "status: busy"
+        },
+    )
+    .unwrap();
+}
+
+fn bench_refresh_allocations(c: &mut Criterion) {
+    let lines = log_lines();
+    let mut scr = headless_screen(ROWS, COLS);
+
+    // Warm-up: buffers, style and transition tables, scroll tables
+    for n in 0..lines.len() * 2 {
+        draw(&mut scr, &lines, n);
+        render_frame(&mut scr).unwrap();
+    }
+
+    let mut allocations = 0;
+    for n in 0..lines.len() {
+        draw(&mut scr, &lines, n);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        render_frame(&mut scr).unwrap();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    assert_eq!(allocations, 0, "refresh allocated after warm-up");
+
+    let mut n = 0;
+    c.bench_function("refresh_log_scroll_60x200", |b| {
+        b.iter(|| {
+            n += 1;
+            draw(&mut scr, &lines, n);
+            black_box(render_frame(&mut scr).unwrap())
+        });
+    });
+}
+
+criterion_group!(benches, bench_refresh_allocations);
+criterion_main!(benches);
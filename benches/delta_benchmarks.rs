@@ -1,5 +1,5 @@
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
-use zaz::__bench::{DirtyRegion, find_line_diff};
+use zaz::__bench::{DirtyRegion, PackedCell, StyleId, find_diff, find_line_diff, scalar_find_diff};
 use zaz::{Attr, Cell, Color};
 
 fn bench_find_line_diff(c: &mut Criterion) {
@@ -69,10 +69,69 @@ fn bench_find_line_diff(c: &mut Criterion) {
                 .map(|i| Cell::new((b'A' + (i % 26) as u8) as char))
                 .collect();
             let line2: Vec<Cell> = (0..size)
-                .map(|i| Cell::with_style((b'A' + (i % 26) as u8) as char, Attr::BOLD, None, None))
+                .map(|i| {
+                    Cell::with_style(
+                        (b'A' + (i % 26) as u8) as char,
+                        Attr::BOLD,
+                        Color::Reset,
+                        Color::Reset,
+                    )
+                })
                 .collect();
             b.iter(|| black_box(find_line_diff(&line1, &line2)));
         });
+
+        // Same cases on interned cells (character + style id), as the screen stores them
+        let packed_line = |style: u32| -> Vec<PackedCell> {
+            (0..*size)
+                .map(|i| PackedCell::new((b'A' + (i % 26) as u8) as char, StyleId::new(style)))
+                .collect()
+        };
+
+        group.bench_with_input(BenchmarkId::new("packed_identical", size), size, |b, _| {
+            let line1 = packed_line(0);
+            let line2 = line1.clone();
+            b.iter(|| black_box(find_line_diff(&line1, &line2)));
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("packed_middle_diff", size),
+            size,
+            |b, &size| {
+                let line1 = packed_line(0);
+                let mut line2 = line1.clone();
+                if size > 0 {
+                    line2[size / 2] = PackedCell::new('X', StyleId::DEFAULT);
+                }
+                b.iter(|| black_box(find_line_diff(&line1, &line2)));
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("packed_style_diff", size), size, |b, _| {
+            let line1 = packed_line(0);
+            let line2 = packed_line(1);
+            b.iter(|| black_box(find_line_diff(&line1, &line2)));
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_word_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("word_diff");
+
+    for size in [80, 200, 1000].iter() {
+        // One change in the middle: both scans run half the line
+        let old: Vec<u64> = (0..*size as u64).collect();
+        let mut new = old.clone();
+        new[size / 2] = u64::MAX;
+
+        group.bench_with_input(BenchmarkId::new("simd", size), size, |b, _| {
+            b.iter(|| black_box(find_diff(&old, &new)));
+        });
+        group.bench_with_input(BenchmarkId::new("scalar", size), size, |b, _| {
+            b.iter(|| black_box(scalar_find_diff(&old, &new)));
+        });
     }
 
     group.finish();
@@ -143,6 +202,7 @@ fn bench_buffer_swap(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_find_line_diff,
+    bench_word_diff,
     bench_dirty_region_operations,
     bench_buffer_swap,
 );
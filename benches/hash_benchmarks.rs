@@ -1,7 +1,7 @@
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use zaz::__bench::hash_line;
+use zaz::__bench::{PackedCell, StyleId, hash_line, hash_words, scalar_hash_words};
 use zaz::{Attr, Cell, Color};
 
 // Current hash implementation (multiplication-based)
@@ -95,6 +95,36 @@ fn bench_hash_functions(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("xxhash_simple", size), &line, |b, line| {
             b.iter(|| black_box(hash_line_xxhash_simple(line)));
         });
+
+        // The same line as the screen stores it: one style id per distinct style
+        let packed: Vec<PackedCell> = (0..*size)
+            .map(|i| {
+                PackedCell::new(
+                    (b'A' + (i % 26) as u8) as char,
+                    StyleId::new((i % 30) as u32),
+                )
+            })
+            .collect();
+        group.bench_with_input(BenchmarkId::new("packed", size), &packed, |b, line| {
+            b.iter(|| black_box(hash_line(line)));
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_word_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("word_hash");
+
+    for size in [80, 200, 1000].iter() {
+        let words: Vec<u64> = (0..*size as u64).map(|i| i << 32 | i % 30).collect();
+
+        group.bench_with_input(BenchmarkId::new("simd", size), &words, |b, words| {
+            b.iter(|| black_box(hash_words(words)));
+        });
+        group.bench_with_input(BenchmarkId::new("scalar", size), &words, |b, words| {
+            b.iter(|| black_box(scalar_hash_words(words)));
+        });
     }
 
     group.finish();
@@ -168,6 +198,25 @@ fn bench_hash_screen_lines(c: &mut Criterion) {
                 });
             },
         );
+
+        let packed: Vec<Vec<PackedCell>> = screen
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|cell| PackedCell::new(cell.ch, StyleId::DEFAULT))
+                    .collect()
+            })
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("full_screen_packed", format!("{}x{}", rows, cols)),
+            &packed,
+            |b, screen: &Vec<Vec<PackedCell>>| {
+                b.iter(|| {
+                    let hashes: Vec<u64> = screen.iter().map(|line| hash_line(line)).collect();
+                    black_box(hashes)
+                });
+            },
+        );
     }
 
     group.finish();
@@ -176,6 +225,7 @@ fn bench_hash_screen_lines(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_hash_functions,
+    bench_word_hash,
     bench_hash_collision_rate,
     bench_hash_consistency,
     bench_hash_screen_lines,
@@ -1,5 +1,6 @@
-use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
 use std::fmt::Write as FmtWrite;
+use zaz::__bench::mvcur;
 use zaz::{Attr, Cell, Color};
 
 // Simulate output buffer operations
@@ -225,11 +226,11 @@ fn bench_line_rendering(c: &mut Criterion) {
 
         group.bench_with_input(BenchmarkId::new("full_line", width), &cells, |b, cells| {
             let mut buffer = String::with_capacity(2000);
-            let mut last_style = (Attr::NORMAL, None, None);
+            let mut last_style = (Attr::NORMAL, Color::Reset, Color::Reset);
 
             b.iter(|| {
                 buffer.clear();
-                last_style = (Attr::NORMAL, None, None);
+                last_style = (Attr::NORMAL, Color::Reset, Color::Reset);
 
                 for cell in cells {
                     let current_style = (cell.attr, cell.fg(), cell.bg());
@@ -263,8 +264,8 @@ fn bench_full_screen_simulation(c: &mut Criterion) {
                             } else {
                                 Attr::NORMAL
                             },
-                            None,
-                            None,
+                            Color::Reset,
+                            Color::Reset,
                         )
                     })
                     .collect()
@@ -294,6 +295,57 @@ fn bench_full_screen_simulation(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_cursor_movement(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cursor_movement");
+
+    // Typical refresh moves: next line start, short hops within a line,
+    // jumps back up to an input field
+    let moves: Vec<((u16, u16), (u16, u16))> = (0..23)
+        .flat_map(|y| {
+            [
+                ((y, 79), (y + 1, 0)),
+                ((y + 1, 4), (y + 1, 9)),
+                ((y + 1, 40), (y + 1, 37)),
+                ((y + 1, 60), (2, 10)),
+            ]
+        })
+        .collect();
+
+    let mut absolute = String::new();
+    for &(_, (y, x)) in &moves {
+        write!(absolute, "\x1b[{};{}H", y + 1, x + 1).unwrap();
+    }
+    group.throughput(Throughput::Bytes(absolute.len() as u64));
+    group.bench_function("always_cup", |b| {
+        let mut buffer = String::with_capacity(absolute.len());
+        b.iter(|| {
+            buffer.clear();
+            for &(_, (y, x)) in &moves {
+                write!(buffer, "\x1b[{};{}H", y + 1, x + 1).unwrap();
+            }
+            black_box(&buffer);
+        });
+    });
+
+    let mut planned = String::new();
+    for &(from, to) in &moves {
+        mvcur(&mut planned, Some(from), to, None);
+    }
+    group.throughput(Throughput::Bytes(planned.len() as u64));
+    group.bench_function("mvcur", |b| {
+        let mut buffer = String::with_capacity(planned.len());
+        b.iter(|| {
+            buffer.clear();
+            for &(from, to) in &moves {
+                mvcur(&mut buffer, Some(from), to, None);
+            }
+            black_box(&buffer);
+        });
+    });
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_ansi_sequence_generation,
@@ -302,5 +354,6 @@ criterion_group!(
     bench_rle_operations,
     bench_line_rendering,
     bench_full_screen_simulation,
+    bench_cursor_movement,
 );
 criterion_main!(benches);
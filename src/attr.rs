@@ -1,7 +1,8 @@
+use smallvec::SmallVec;
 use std::ops::{BitAnd, BitOr, Not};
 
 /// Text attributes
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Attr(pub(crate) u16);
 
 impl Attr {
@@ -31,38 +32,29 @@ impl Attr {
         self.0 == 0
     }
 
-    pub(crate) fn to_ansi_codes(&self) -> Vec<&'static str> {
-        let mut codes = Vec::new();
-
-        if self.contains(Attr::BOLD) {
-            codes.push("1");
-        }
-        if self.contains(Attr::DIM) {
-            codes.push("2");
-        }
-        if self.contains(Attr::ITALIC) {
-            codes.push("3");
-        }
-        if self.contains(Attr::UNDERLINE) {
-            codes.push("4");
-        }
-        if self.contains(Attr::BLINK) {
-            codes.push("5");
-        }
-        if self.contains(Attr::REVERSE) {
-            codes.push("7");
-        }
-        if self.contains(Attr::HIDDEN) {
-            codes.push("8");
-        }
-        if self.contains(Attr::STRIKETHROUGH) {
-            codes.push("9");
-        }
-
-        codes
+    pub(crate) fn to_ansi_codes(&self) -> SmallVec<[&'static str; 8]> {
+        SGR_CODES
+            .iter()
+            .filter(|(attr, _, _)| self.contains(*attr))
+            .map(|(_, on, _)| *on)
+            .collect()
     }
 }
 
+/// SGR codes that turn each attribute on and off
+///
+/// Bold and dim share their "off" code (22, normal intensity).
+pub(crate) const SGR_CODES: [(Attr, &str, &str); 8] = [
+    (Attr::BOLD, "1", "22"),
+    (Attr::DIM, "2", "22"),
+    (Attr::ITALIC, "3", "23"),
+    (Attr::UNDERLINE, "4", "24"),
+    (Attr::BLINK, "5", "25"),
+    (Attr::REVERSE, "7", "27"),
+    (Attr::HIDDEN, "8", "28"),
+    (Attr::STRIKETHROUGH, "9", "29"),
+];
+
 impl BitOr for Attr {
     type Output = Self;
 
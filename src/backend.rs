@@ -1,14 +1,19 @@
 use crate::error::{Error, Result};
-use crate::input::Key;
+use crate::input::{Key, Reply};
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
 use std::sync::{Mutex, OnceLock};
 
 static BACKEND: OnceLock<Mutex<Backend>> = OnceLock::new();
 static UPDATE_BUFFER: OnceLock<Mutex<String>> = OnceLock::new();
+// Input read while waiting for replies, handed out by read_key before stdin
+static PENDING_INPUT: OnceLock<Mutex<VecDeque<u8>>> = OnceLock::new();
 
 pub(crate) struct Backend {
     original_termios: Option<Termios>,
     initialized: bool,
+    // Titles saved with push_title that still need restoring on cleanup
+    pushed_titles: usize,
 }
 
 #[cfg(unix)]
@@ -29,6 +34,7 @@ impl Backend {
         Self {
             original_termios: None,
             initialized: false,
+            pushed_titles: 0,
         }
     }
 
@@ -47,8 +53,8 @@ impl Backend {
         print!("\x1b[?1049h");
         // Hide cursor
         print!("\x1b[?25l");
-        // Clear screen
-        print!("\x1b[2J");
+        // Clear screen and home the cursor
+        print!("\x1b[2J\x1b[H");
         io::stdout().flush()?;
 
         Ok(())
@@ -62,6 +68,12 @@ impl Backend {
             return Ok(());
         }
 
+        // Restore window titles saved with push_title
+        for _ in 0..guard.pushed_titles {
+            print!("{}", crate::osc::pop_title_sequence());
+        }
+        guard.pushed_titles = 0;
+
         // Show cursor
         print!("\x1b[?25h");
         // Exit alternate screen
@@ -74,6 +86,41 @@ impl Backend {
         Ok(())
     }
 
+    /// Record that a window title was pushed onto the terminal's stack
+    pub(crate) fn title_pushed() {
+        if let Some(backend) = BACKEND.get() {
+            backend.lock().unwrap().pushed_titles += 1;
+        }
+    }
+
+    /// Record that a pushed window title was popped
+    pub(crate) fn title_popped() {
+        if let Some(backend) = BACKEND.get() {
+            let mut guard = backend.lock().unwrap();
+            guard.pushed_titles = guard.pushed_titles.saturating_sub(1);
+        }
+    }
+
+    /// Host name for OSC 7 working directory reports
+    pub(crate) fn hostname() -> String {
+        #[cfg(unix)]
+        {
+            let mut buf = [0u8; 256];
+            let result =
+                unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+            if result != 0 {
+                return String::new();
+            }
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        }
+
+        #[cfg(not(unix))]
+        {
+            String::new()
+        }
+    }
+
     #[cfg(unix)]
     fn enable_raw_mode(&mut self) -> Result<()> {
         let fd = io::stdin().as_raw_fd();
@@ -138,6 +185,10 @@ impl Backend {
             let mut stdin = io::stdin();
             let fd = stdin.as_raw_fd();
 
+            if let Some(byte) = Self::pop_pending_input() {
+                return Self::parse_key_from_byte(byte, &mut stdin, &mut buf).map(Some);
+            }
+
             if let Some(timeout) = timeout_ms {
                 // Use select to wait for input with timeout
                 unsafe {
@@ -184,6 +235,107 @@ impl Backend {
         }
     }
 
+    /// Read terminal replies until `done` accepts one or the timeout expires
+    ///
+    /// Returns every reply received so far. Bytes that are not replies (for
+    /// example keys typed while waiting) are kept for [`Backend::read_key`]
+    /// and [`Backend::read_key_timeout`] to return first.
+    pub(crate) fn read_replies(
+        timeout_ms: u64,
+        mut done: impl FnMut(&Reply) -> bool,
+    ) -> Result<Vec<Reply>> {
+        #[cfg(unix)]
+        {
+            use std::time::{Duration, Instant};
+
+            let fd = io::stdin().as_raw_fd();
+
+            // Nobody will answer on a pipe or file
+            if unsafe { libc::isatty(fd) } == 0 {
+                return Ok(Vec::new());
+            }
+
+            let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+            let mut pending = Vec::new();
+            let mut input = Vec::new();
+            let mut replies = Vec::new();
+            let mut buf = [0u8; 256];
+
+            let result = loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break Ok(());
+                }
+
+                let mut fds = [libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                }];
+                let timeout = remaining.as_millis().max(1) as libc::c_int;
+                let result = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) };
+
+                if result < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    break Err(Error::Io(err));
+                } else if result == 0 {
+                    break Ok(()); // Timeout
+                }
+
+                // Read straight from the fd so nothing is left behind in
+                // std's stdin buffer where poll() can't see it
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    break Err(Error::Io(err));
+                } else if n == 0 {
+                    break Ok(());
+                }
+
+                pending.extend_from_slice(&buf[..n as usize]);
+                let (parsed, consumed) = Reply::parse_all_keeping(&pending, &mut input);
+                pending.drain(..consumed);
+
+                let mut finished = false;
+                for reply in parsed {
+                    finished = finished || done(&reply);
+                    replies.push(reply);
+                }
+                if finished {
+                    break Ok(());
+                }
+            };
+
+            // Keys typed meanwhile, including a sequence cut off at the end,
+            // are returned by the next read_key
+            input.append(&mut pending);
+            Self::pending_input().lock().unwrap().extend(input);
+            result.map(|()| replies)
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = (timeout_ms, &mut done);
+            Err(Error::NotSupported)
+        }
+    }
+
+    /// Input read while waiting for replies
+    fn pending_input() -> &'static Mutex<VecDeque<u8>> {
+        PENDING_INPUT.get_or_init(|| Mutex::new(VecDeque::new()))
+    }
+
+    /// Take the next byte of input read while waiting for replies
+    fn pop_pending_input() -> Option<u8> {
+        Self::pending_input().lock().unwrap().pop_front()
+    }
+
     fn parse_key_from_byte(byte: u8, stdin: &mut io::Stdin, buf: &mut [u8; 8]) -> Result<Key> {
         // Handle special ASCII characters
         match byte {
@@ -204,16 +356,18 @@ impl Backend {
                     std::thread::sleep(Duration::from_millis(1));
 
                     loop {
-                        match stdin.read(&mut buf[..1]) {
-                            Ok(0) => break,
-                            Ok(_) => {
-                                seq.push(buf[0]);
-                                if seq.len() >= 6 {
-                                    break;
-                                }
-                            }
-                            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
-                            Err(e) => return Err(e.into()),
+                        let byte = match Self::pop_pending_input() {
+                            Some(byte) => byte,
+                            None => match stdin.read(&mut buf[..1]) {
+                                Ok(0) => break,
+                                Ok(_) => buf[0],
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                                Err(e) => return Err(e.into()),
+                            },
+                        };
+                        seq.push(byte);
+                        if seq.len() >= 6 {
+                            break;
                         }
                     }
                 }
@@ -240,6 +394,10 @@ impl Backend {
         let mut buf = [0u8; 8];
         let mut stdin = io::stdin();
 
+        if let Some(byte) = Self::pop_pending_input() {
+            return Self::parse_key_from_byte(byte, &mut stdin, &mut buf);
+        }
+
         let n = stdin.read(&mut buf[..1])?;
         if n == 0 {
             return Ok(Key::Unknown);
@@ -309,6 +467,7 @@ mod tests {
         let backend = Backend::new();
         assert!(!backend.initialized);
         assert!(backend.original_termios.is_none());
+        assert_eq!(backend.pushed_titles, 0);
     }
 
     #[test]
@@ -0,0 +1,202 @@
+/// Terminal capability detection
+///
+/// Capabilities are discovered by sending a batch of queries and parsing the
+/// replies. Primary device attributes (DA1) is sent last: every terminal
+/// answers it and replies arrive in order, so its reply marks the end of the
+/// batch without waiting for the full timeout.
+use crate::image::ImageProtocol;
+use crate::input::Reply;
+use crate::kitty::KittyFlags;
+
+/// DEC private mode for synchronized output
+pub(crate) const MODE_SYNCHRONIZED_OUTPUT: u16 = 2026;
+/// DEC private mode for bracketed paste
+pub(crate) const MODE_BRACKETED_PASTE: u16 = 2004;
+/// DEC private mode for focus in/out reporting
+pub(crate) const MODE_FOCUS_EVENTS: u16 = 1004;
+
+/// Terminals (by XTVERSION name) known to implement REP and back color erase
+///
+/// Neither feature can be queried, so they're enabled for these only. Names
+/// are lowercase and matched case-insensitively, as xterm replies `XTerm(390)`.
+const REP_AND_BCE_TERMINALS: &[&str] = &[
+    "xterm", "kitty", "foot", "wezterm", "ghostty", "contour", "tmux",
+];
+
+/// Image id used for the Kitty graphics query
+const KITTY_QUERY_ID: u32 = 31;
+
+/// Queries sent by [`Screen::probe`](crate::Screen::probe), in order
+pub(crate) const PROBE_SEQUENCE: &str = concat!(
+    "\x1b[>q",                                    // XTVERSION
+    "\x1b[?2026$p",                               // DECRQM synchronized output
+    "\x1b[?2004$p",                               // DECRQM bracketed paste
+    "\x1b[?1004$p",                               // DECRQM focus events
+    "\x1b[?u",                                    // Kitty keyboard flags
+    "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\", // Kitty graphics query (1x1 RGB)
+    "\x1b[c",                                     // Primary device attributes (sentinel)
+);
+
+/// Terminal capabilities reported by the terminal itself
+///
+/// Obtained from [`Screen::probe`](crate::Screen::probe). Everything defaults
+/// to unsupported, which is also what a terminal that doesn't answer gets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Attributes from the primary device attributes (DA1) reply
+    pub device_attributes: Vec<u16>,
+    /// Terminal name and version from XTVERSION, e.g. `kitty(0.31.0)`
+    pub version: Option<String>,
+    /// Sixel graphics (DA1 attribute 4)
+    pub sixel: bool,
+    /// Synchronized output (DEC mode 2026)
+    pub synchronized_output: bool,
+    /// Bracketed paste (DEC mode 2004)
+    pub bracketed_paste: bool,
+    /// Focus in/out reporting (DEC mode 1004)
+    pub focus_events: bool,
+    /// Kitty keyboard protocol flags in effect, if the protocol is supported
+    pub kitty_keyboard: Option<KittyFlags>,
+    /// Kitty graphics protocol
+    pub kitty_graphics: bool,
+    /// REP (`CSI n b`) repeats the preceding character
+    pub rep: bool,
+    /// Erase operations (ECH, EL) fill with the current background color
+    pub back_color_erase: bool,
+}
+
+impl Capabilities {
+    /// Build capabilities from the replies to [`PROBE_SEQUENCE`]
+    pub(crate) fn from_replies(replies: &[Reply]) -> Self {
+        let mut caps = Self::default();
+        for reply in replies {
+            caps.apply(reply);
+        }
+        caps
+    }
+
+    /// Record what a single reply tells us
+    pub(crate) fn apply(&mut self, reply: &Reply) {
+        match reply {
+            Reply::PrimaryAttributes(attrs) => {
+                self.sixel = attrs.iter().skip(1).any(|&a| a == 4);
+                self.device_attributes = attrs.clone();
+            }
+            Reply::Version(version) => {
+                let lower = version.to_ascii_lowercase();
+                let known = REP_AND_BCE_TERMINALS
+                    .iter()
+                    .any(|name| lower.starts_with(name));
+                self.rep = known;
+                self.back_color_erase = known;
+                self.version = Some(version.clone());
+            }
+            Reply::Mode { mode, status } => {
+                // 0 = not recognized, 4 = permanently reset
+                let supported = matches!(status, 1..=3);
+                match *mode {
+                    MODE_SYNCHRONIZED_OUTPUT => self.synchronized_output = supported,
+                    MODE_BRACKETED_PASTE => self.bracketed_paste = supported,
+                    MODE_FOCUS_EVENTS => self.focus_events = supported,
+                    _ => {}
+                }
+            }
+            Reply::KittyKeyboard(flags) => {
+                self.kitty_keyboard = Some(KittyFlags::from_bits_truncate(*flags));
+            }
+            Reply::KittyGraphics { id, message } => {
+                if *id == KITTY_QUERY_ID {
+                    self.kitty_graphics = message == "OK";
+                }
+            }
+            Reply::Clipboard { .. } | Reply::Color { .. } => {}
+        }
+    }
+
+    /// Best image protocol available, preferring Kitty over Sixel
+    pub fn image_protocol(&self) -> Option<ImageProtocol> {
+        if self.kitty_graphics {
+            Some(ImageProtocol::Kitty)
+        } else if self.sixel {
+            Some(ImageProtocol::Sixel)
+        } else {
+            None
+        }
+    }
+
+    /// Check if the Kitty keyboard protocol can be enabled
+    pub fn supports_kitty_keyboard(&self) -> bool {
+        self.kitty_keyboard.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_unsupported() {
+        let caps = Capabilities::default();
+        assert!(!caps.synchronized_output);
+        assert!(!caps.supports_kitty_keyboard());
+        assert_eq!(caps.image_protocol(), None);
+    }
+
+    #[test]
+    fn test_probe_sequence_ends_with_da1() {
+        assert!(PROBE_SEQUENCE.ends_with("\x1b[c"));
+        assert!(PROBE_SEQUENCE.contains(&format!("i={}", KITTY_QUERY_ID)));
+    }
+
+    #[test]
+    fn test_from_replies_kitty() {
+        let (replies, _) = Reply::parse_all(
+            b"\x1bP>|kitty(0.31.0)\x1b\\\x1b[?2026;2$y\x1b[?2004;2$y\x1b[?1004;2$y\
+              \x1b[?0u\x1b_Gi=31;OK\x1b\\\x1b[?62;22c",
+        );
+        let caps = Capabilities::from_replies(&replies);
+
+        assert_eq!(caps.version.as_deref(), Some("kitty(0.31.0)"));
+        assert!(caps.synchronized_output);
+        assert!(caps.bracketed_paste);
+        assert!(caps.focus_events);
+        assert_eq!(caps.kitty_keyboard, Some(KittyFlags::empty()));
+        assert!(caps.kitty_graphics);
+        assert!(caps.rep);
+        assert!(caps.back_color_erase);
+        assert!(!caps.sixel);
+        assert_eq!(caps.device_attributes, vec![62, 22]);
+        assert_eq!(caps.image_protocol(), Some(ImageProtocol::Kitty));
+    }
+
+    #[test]
+    fn test_from_replies_xterm_version() {
+        let (replies, _) = Reply::parse_all(b"\x1bP>|XTerm(390)\x1b\\\x1b[?64;4c");
+        let caps = Capabilities::from_replies(&replies);
+
+        assert_eq!(caps.version.as_deref(), Some("XTerm(390)"));
+        assert!(caps.rep);
+        assert!(caps.back_color_erase);
+    }
+
+    #[test]
+    fn test_from_replies_sixel_terminal() {
+        // Only answers DA1, advertising sixel, and reports 2026 as unknown
+        let (replies, _) = Reply::parse_all(b"\x1b[?2026;0$y\x1b[?63;1;4;22c");
+        let caps = Capabilities::from_replies(&replies);
+
+        assert!(caps.sixel);
+        assert!(!caps.synchronized_output);
+        assert!(!caps.rep);
+        assert!(!caps.back_color_erase);
+        assert!(!caps.supports_kitty_keyboard());
+        assert_eq!(caps.image_protocol(), Some(ImageProtocol::Sixel));
+    }
+
+    #[test]
+    fn test_kitty_graphics_error_reply() {
+        let (replies, _) = Reply::parse_all(b"\x1b_Gi=31;ENOTSUPPORTED:nope\x1b\\");
+        let caps = Capabilities::from_replies(&replies);
+        assert!(!caps.kitty_graphics);
+    }
+}
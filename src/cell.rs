@@ -7,12 +7,13 @@ use crate::color::Color;
 
 /// A single cell in the screen buffer, containing a character and its styling
 ///
-/// Memory layout (16 bytes total):
+/// Memory layout (20 bytes total):
 /// - ch: char (4 bytes)
 /// - attr: u16 (2 bytes)
-/// - padding: 2 bytes (for alignment)
+/// - hyperlink: u16 (2 bytes)
 /// - fg: Color (4 bytes)
 /// - bg: Color (4 bytes)
+/// - pair: u16 (2 bytes, +2 padding)
 ///
 /// Uses Color::Reset to represent terminal default colors (similar to ratatui's approach)
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,6 +26,10 @@ pub struct Cell {
     pub fg: Color,
     /// Background color (Color::Reset = terminal default)
     pub bg: Color,
+    /// Hyperlink id into the Screen's URI table (0 = no link)
+    pub hyperlink: u16,
+    /// Color pair the fg/bg came from (0 = none), recolored when the pair changes
+    pub pair: u16,
 }
 
 impl Cell {
@@ -35,6 +40,8 @@ impl Cell {
             attr: Attr::NORMAL,
             fg: Color::Reset,
             bg: Color::Reset,
+            hyperlink: 0,
+            pair: 0,
         }
     }
 
@@ -45,7 +52,14 @@ impl Cell {
 
     /// Create a cell with a character and specific styling
     pub fn with_style(ch: char, attr: Attr, fg: Color, bg: Color) -> Self {
-        Self { ch, attr, fg, bg }
+        Self {
+            ch,
+            attr,
+            fg,
+            bg,
+            hyperlink: 0,
+            pair: 0,
+        }
     }
 
     /// Get the character
@@ -72,6 +86,18 @@ impl Cell {
         self.bg
     }
 
+    /// Get the hyperlink id (0 = no link)
+    #[inline]
+    pub fn hyperlink(&self) -> u16 {
+        self.hyperlink
+    }
+
+    /// Get the color pair id (0 = none)
+    #[inline]
+    pub fn pair(&self) -> u16 {
+        self.pair
+    }
+
     /// Set the foreground color
     #[inline]
     pub fn set_fg(&mut self, color: Color) -> &mut Self {
@@ -86,17 +112,28 @@ impl Cell {
         self
     }
 
+    /// Set the hyperlink id (0 = no link)
+    #[inline]
+    pub fn set_hyperlink(&mut self, id: u16) -> &mut Self {
+        self.hyperlink = id;
+        self
+    }
+
     /// Check if this cell is a blank (space with no styling)
     pub fn is_blank(&self) -> bool {
         self.ch == ' '
             && self.attr == Attr::NORMAL
             && self.fg == Color::Reset
             && self.bg == Color::Reset
+            && self.hyperlink == 0
     }
 
     /// Check if this cell has the same styling as another (ignoring character)
     pub fn same_style(&self, other: &Cell) -> bool {
-        self.attr == other.attr && self.fg == other.fg && self.bg == other.bg
+        self.attr == other.attr
+            && self.fg == other.fg
+            && self.bg == other.bg
+            && self.hyperlink == other.hyperlink
     }
 }
 
@@ -114,8 +151,8 @@ mod tests {
     fn test_cell_size() {
         let size = std::mem::size_of::<Cell>();
 
-        // Color enum: Cell should be 16 bytes (char=4, Attr=2, padding=2, fg=4, bg=4)
-        assert_eq!(size, 16, "Cell should be exactly 16 bytes");
+        // Color enum: Cell should be 20 bytes (char=4, Attr=2, hyperlink=2, fg=4, bg=4, pair=2+2)
+        assert_eq!(size, 20, "Cell should be exactly 20 bytes");
         assert!(
             size < 24,
             "Cell should be significantly smaller than original ~32 bytes"
@@ -193,6 +230,20 @@ mod tests {
         assert!(!cell1.same_style(&cell3));
     }
 
+    #[test]
+    fn test_cell_hyperlink() {
+        let mut linked = Cell::new('A');
+        linked.set_hyperlink(3);
+
+        assert_eq!(linked.hyperlink(), 3);
+        assert!(!linked.same_style(&Cell::new('A')));
+        assert_ne!(linked, Cell::new('A'));
+
+        let mut linked_blank = Cell::blank();
+        linked_blank.set_hyperlink(1);
+        assert!(!linked_blank.is_blank());
+    }
+
     #[test]
     fn test_cell_default() {
         let cell = Cell::default();
@@ -287,10 +338,10 @@ mod tests {
 
         // Verify it's significantly smaller than original
         // Original was ~32 bytes, so 80 cells = 2560 bytes
-        // New should be 16 bytes, so 80 cells = 1280 bytes
+        // New should be 20 bytes, so 80 cells = 1600 bytes
         assert_eq!(
-            size, 1280,
-            "80 cells should use exactly 1280 bytes (16 bytes per cell)"
+            size, 1600,
+            "80 cells should use exactly 1600 bytes (20 bytes per cell)"
         );
     }
 }
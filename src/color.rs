@@ -1,5 +1,5 @@
 /// Terminal colors
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Color {
     Black,
     Red,
@@ -23,6 +23,17 @@ pub enum Color {
     Reset,
 }
 
+/// SGR foreground codes of the 16 basic colors, by palette index
+const FG_CODES: [&str; 16] = [
+    "30", "31", "32", "33", "34", "35", "36", "37", "90", "91", "92", "93", "94", "95", "96", "97",
+];
+
+/// SGR background codes of the 16 basic colors, by palette index
+const BG_CODES: [&str; 16] = [
+    "40", "41", "42", "43", "44", "45", "46", "47", "100", "101", "102", "103", "104", "105",
+    "106", "107",
+];
+
 impl Color {
     /// Convert color to (discriminant, data) for efficient hashing
     /// Returns (type_byte, data_u32) to minimize branches in hash functions
@@ -51,58 +62,83 @@ impl Color {
         }
     }
 
-    /// Write foreground ANSI code directly to a string buffer (zero-allocation for basic colors)
+    /// Write foreground ANSI code directly to a string buffer (zero-allocation)
     pub(crate) fn write_ansi_fg(&self, buf: &mut String) {
-        use std::fmt::Write;
         match self {
-            Color::Black => buf.push_str("30"),
-            Color::Red => buf.push_str("31"),
-            Color::Green => buf.push_str("32"),
-            Color::Yellow => buf.push_str("33"),
-            Color::Blue => buf.push_str("34"),
-            Color::Magenta => buf.push_str("35"),
-            Color::Cyan => buf.push_str("36"),
-            Color::White => buf.push_str("37"),
-            Color::BrightBlack => buf.push_str("90"),
-            Color::BrightRed => buf.push_str("91"),
-            Color::BrightGreen => buf.push_str("92"),
-            Color::BrightYellow => buf.push_str("93"),
-            Color::BrightBlue => buf.push_str("94"),
-            Color::BrightMagenta => buf.push_str("95"),
-            Color::BrightCyan => buf.push_str("96"),
-            Color::BrightWhite => buf.push_str("97"),
-            Color::Rgb(r, g, b) => write!(buf, "38;2;{};{};{}", r, g, b).unwrap(),
-            Color::Ansi256(c) => write!(buf, "38;5;{}", c).unwrap(),
             Color::Reset => buf.push_str("39"),
+            _ => self.write_ansi(buf, &FG_CODES, "38"),
         }
     }
 
-    /// Write background ANSI code directly to a string buffer (zero-allocation for basic colors)
+    /// Write background ANSI code directly to a string buffer (zero-allocation)
     pub(crate) fn write_ansi_bg(&self, buf: &mut String) {
-        use std::fmt::Write;
         match self {
-            Color::Black => buf.push_str("40"),
-            Color::Red => buf.push_str("41"),
-            Color::Green => buf.push_str("42"),
-            Color::Yellow => buf.push_str("43"),
-            Color::Blue => buf.push_str("44"),
-            Color::Magenta => buf.push_str("45"),
-            Color::Cyan => buf.push_str("46"),
-            Color::White => buf.push_str("47"),
-            Color::BrightBlack => buf.push_str("100"),
-            Color::BrightRed => buf.push_str("101"),
-            Color::BrightGreen => buf.push_str("102"),
-            Color::BrightYellow => buf.push_str("103"),
-            Color::BrightBlue => buf.push_str("104"),
-            Color::BrightMagenta => buf.push_str("105"),
-            Color::BrightCyan => buf.push_str("106"),
-            Color::BrightWhite => buf.push_str("107"),
-            Color::Rgb(r, g, b) => write!(buf, "48;2;{};{};{}", r, g, b).unwrap(),
-            Color::Ansi256(c) => write!(buf, "48;5;{}", c).unwrap(),
             Color::Reset => buf.push_str("49"),
+            _ => self.write_ansi(buf, &BG_CODES, "48"),
+        }
+    }
+
+    /// Basic colors from `basic`, others as `<extended>;2;r;g;b` or
+    /// `<extended>;5;index`
+    fn write_ansi(&self, buf: &mut String, basic: &[&str; 16], extended: &str) {
+        use crate::writer::push_decimal;
+        match *self {
+            Color::Rgb(r, g, b) => {
+                buf.push_str(extended);
+                buf.push_str(";2;");
+                push_decimal(buf, r as u32);
+                buf.push(';');
+                push_decimal(buf, g as u32);
+                buf.push(';');
+                push_decimal(buf, b as u32);
+            }
+            Color::Ansi256(c) => {
+                buf.push_str(extended);
+                buf.push_str(";5;");
+                push_decimal(buf, c as u32);
+            }
+            _ => {
+                if let Some(index) = self.palette_index() {
+                    buf.push_str(basic[index as usize]);
+                }
+            }
         }
     }
 
+    /// Index of this color in the terminal's 256-color palette
+    pub(crate) fn palette_index(&self) -> Option<u8> {
+        match self {
+            Color::Black => Some(0),
+            Color::Red => Some(1),
+            Color::Green => Some(2),
+            Color::Yellow => Some(3),
+            Color::Blue => Some(4),
+            Color::Magenta => Some(5),
+            Color::Cyan => Some(6),
+            Color::White => Some(7),
+            Color::BrightBlack => Some(8),
+            Color::BrightRed => Some(9),
+            Color::BrightGreen => Some(10),
+            Color::BrightYellow => Some(11),
+            Color::BrightBlue => Some(12),
+            Color::BrightMagenta => Some(13),
+            Color::BrightCyan => Some(14),
+            Color::BrightWhite => Some(15),
+            Color::Ansi256(c) => Some(*c),
+            Color::Rgb(..) | Color::Reset => None,
+        }
+    }
+
+    /// RGB value of this color, using xterm's default palette for indexed colors
+    ///
+    /// Returns `None` for [`Color::Reset`], whose value only the terminal knows.
+    pub(crate) fn to_rgb(self) -> Option<(u8, u8, u8)> {
+        if let Color::Rgb(r, g, b) = self {
+            return Some((r, g, b));
+        }
+        self.palette_index().map(palette_rgb)
+    }
+
     // Keep old methods for backward compatibility (used in tests and mosaic)
     pub(crate) fn to_ansi_fg(&self) -> String {
         let mut buf = String::with_capacity(16);
@@ -117,6 +153,103 @@ impl Color {
     }
 }
 
+/// Whether the terminal has a light or dark background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Classify a background color by its perceived brightness
+    pub fn from_background(background: Color) -> Option<Theme> {
+        let (r, g, b) = background.to_rgb()?;
+        Some(if crate::mosaic::Rgb::new(r, g, b).luminance() >= 128 {
+            Theme::Light
+        } else {
+            Theme::Dark
+        })
+    }
+}
+
+/// Colors the terminal reported for its default foreground, background and palette
+///
+/// Obtained from [`Screen::query_default_colors`](crate::Screen::query_default_colors).
+/// Entries the terminal didn't answer are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalColors {
+    /// Default foreground (OSC 10)
+    pub foreground: Option<Color>,
+    /// Default background (OSC 11)
+    pub background: Option<Color>,
+    /// The 16 ANSI palette entries (OSC 4)
+    pub palette: [Option<Color>; 16],
+}
+
+impl TerminalColors {
+    /// Build from the color reports among `replies`
+    pub(crate) fn from_replies(replies: &[crate::input::Reply]) -> Self {
+        let mut colors = Self::default();
+        for reply in replies {
+            if let crate::input::Reply::Color { code, index, color } = *reply {
+                match (code, index) {
+                    (10, _) => colors.foreground = Some(color),
+                    (11, _) => colors.background = Some(color),
+                    (4, Some(i)) if (i as usize) < colors.palette.len() => {
+                        colors.palette[i as usize] = Some(color)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        colors
+    }
+
+    /// Light or dark, based on the background color
+    pub fn theme(&self) -> Option<Theme> {
+        Theme::from_background(self.background?)
+    }
+}
+
+/// xterm's default RGB value for a 256-color palette index
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    match index {
+        0..=15 => ANSI[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            (
+                CUBE[(i / 36) as usize],
+                CUBE[(i / 6 % 6) as usize],
+                CUBE[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
+
 /// A color pair consisting of foreground and background colors
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColorPair {
@@ -165,6 +298,52 @@ mod tests {
         assert_ne!(Color::Rgb(255, 0, 0), Color::Rgb(255, 0, 1));
     }
 
+    #[test]
+    fn test_color_to_rgb() {
+        assert_eq!(Color::Rgb(1, 2, 3).to_rgb(), Some((1, 2, 3)));
+        assert_eq!(Color::Red.to_rgb(), Some((0xcd, 0, 0)));
+        assert_eq!(Color::Ansi256(9).to_rgb(), Color::BrightRed.to_rgb());
+        assert_eq!(Color::Ansi256(16).to_rgb(), Some((0, 0, 0)));
+        assert_eq!(Color::Ansi256(196).to_rgb(), Some((255, 0, 0)));
+        assert_eq!(Color::Ansi256(232).to_rgb(), Some((8, 8, 8)));
+        assert_eq!(Color::Ansi256(255).to_rgb(), Some((238, 238, 238)));
+        assert_eq!(Color::Reset.to_rgb(), None);
+    }
+
+    #[test]
+    fn test_theme_from_background() {
+        assert_eq!(
+            Theme::from_background(Color::Rgb(0x1e, 0x1e, 0x2e)),
+            Some(Theme::Dark)
+        );
+        assert_eq!(
+            Theme::from_background(Color::Rgb(0xfd, 0xf6, 0xe3)),
+            Some(Theme::Light)
+        );
+        assert_eq!(Theme::from_background(Color::White), Some(Theme::Light));
+        assert_eq!(Theme::from_background(Color::Reset), None);
+    }
+
+    #[test]
+    fn test_terminal_colors_from_replies() {
+        let (replies, _) = crate::input::Reply::parse_all(
+            b"\x1b]10;rgb:0000/0000/0000\x1b\\\x1b]11;rgb:ffff/ffff/ffff\x1b\\\x1b]4;2;rgb:00/ff/00\x1b\\",
+        );
+        let colors = TerminalColors::from_replies(&replies);
+
+        assert_eq!(colors.foreground, Some(Color::Rgb(0, 0, 0)));
+        assert_eq!(colors.background, Some(Color::Rgb(255, 255, 255)));
+        assert_eq!(colors.palette[2], Some(Color::Rgb(0, 255, 0)));
+        assert_eq!(colors.palette[0], None);
+        assert_eq!(colors.theme(), Some(Theme::Light));
+    }
+
+    #[test]
+    fn test_terminal_colors_no_replies() {
+        let colors = TerminalColors::from_replies(&[]);
+        assert_eq!(colors.theme(), None);
+    }
+
     #[test]
     fn test_color_reset() {
         assert_eq!(Color::Reset.to_ansi_fg(), "39");
@@ -0,0 +1,57 @@
+//! Terminal cursor appearance
+//!
+//! The shape is set with DECSCUSR (`CSI Ps SP q`), which xterm, kitty,
+//! WezTerm, foot, iTerm2 and the Linux console all understand.
+
+/// Shape of the terminal cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorStyle {
+    /// Full cell block
+    Block,
+    /// Line under the character
+    Underline,
+    /// Vertical bar before the character
+    Bar,
+}
+
+impl CursorStyle {
+    /// DECSCUSR parameter for this shape
+    fn decscusr(&self, blinking: bool) -> u8 {
+        let steady = match self {
+            CursorStyle::Block => 2,
+            CursorStyle::Underline => 4,
+            CursorStyle::Bar => 6,
+        };
+        if blinking { steady - 1 } else { steady }
+    }
+}
+
+/// Generate DECSCUSR sequence for the given shape
+pub(crate) fn style_sequence(style: CursorStyle, blinking: bool) -> String {
+    format!("\x1b[{} q", style.decscusr(blinking))
+}
+
+/// Generate DECSCUSR sequence that restores the terminal's default shape
+pub(crate) fn reset_style_sequence() -> &'static str {
+    "\x1b[0 q"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_sequence() {
+        assert_eq!(style_sequence(CursorStyle::Block, true), "\x1b[1 q");
+        assert_eq!(style_sequence(CursorStyle::Block, false), "\x1b[2 q");
+        assert_eq!(style_sequence(CursorStyle::Underline, true), "\x1b[3 q");
+        assert_eq!(style_sequence(CursorStyle::Underline, false), "\x1b[4 q");
+        assert_eq!(style_sequence(CursorStyle::Bar, true), "\x1b[5 q");
+        assert_eq!(style_sequence(CursorStyle::Bar, false), "\x1b[6 q");
+    }
+
+    #[test]
+    fn test_reset_style_sequence() {
+        assert_eq!(reset_style_sequence(), "\x1b[0 q");
+    }
+}
@@ -1,13 +1,22 @@
 use crate::Color;
 use crate::cell::Cell;
+use crate::style::PackedCell;
+use std::collections::HashMap;
 
-/// Represents a dirty region within a line
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Most disjoint dirty spans tracked per line
+///
+/// Marking more than this merges the two spans closest together.
+pub const MAX_DIRTY_SPANS: usize = 4;
+
+/// Represents the dirty columns of a line
+///
+/// Up to [`MAX_DIRTY_SPANS`] disjoint spans, so edits at both ends of a wide
+/// line don't make the unchanged middle dirty too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DirtyRegion {
-    /// First changed column (inclusive), None if line is clean
-    pub first_changed: Option<u16>,
-    /// Last changed column (inclusive), None if line is clean
-    pub last_changed: Option<u16>,
+    /// Sorted, non-adjacent column spans (inclusive); only `..len` are used
+    spans: [(u16, u16); MAX_DIRTY_SPANS],
+    len: u8,
 }
 
 /// Represents a scroll operation (like ncurses' scroll hunks)
@@ -21,58 +30,142 @@ pub struct ScrollOp {
     pub shift: isize,
 }
 
+impl ScrollOp {
+    /// First and last row (inclusive) the scroll moves or erases
+    ///
+    /// Covers both where the lines were and where they end up, i.e. the
+    /// scroll region to set.
+    pub fn region(&self) -> (usize, usize) {
+        let last = self.start + self.size - 1;
+        if self.shift > 0 {
+            (self.start, last + self.shift as usize)
+        } else {
+            (self.start - self.shift.unsigned_abs(), last)
+        }
+    }
+}
+
 impl DirtyRegion {
     /// Create a clean (no changes) dirty region
     pub fn clean() -> Self {
         Self {
-            first_changed: None,
-            last_changed: None,
+            spans: [(0, 0); MAX_DIRTY_SPANS],
+            len: 0,
         }
     }
 
     /// Create a dirty region covering the entire line
     pub fn full(width: u16) -> Self {
-        Self {
-            first_changed: Some(0),
-            last_changed: Some(width.saturating_sub(1)),
-        }
+        let mut region = Self::clean();
+        region.mark(0, width.saturating_sub(1));
+        region
     }
 
     /// Mark a range as dirty
     pub fn mark(&mut self, start: u16, end: u16) {
-        match (self.first_changed, self.last_changed) {
-            (None, None) => {
-                // First time marking this line
-                self.first_changed = Some(start);
-                self.last_changed = Some(end);
-            }
-            (Some(first), Some(last)) => {
-                // Expand the dirty region
-                self.first_changed = Some(first.min(start));
-                self.last_changed = Some(last.max(end));
+        let (mut start, mut end) = (start.min(end), start.max(end));
+        let mut spans = [(0, 0); MAX_DIRTY_SPANS + 1];
+        let mut len = 0;
+        let mut placed = false;
+
+        for &(first, last) in self.spans() {
+            if last.saturating_add(1) < start {
+                // Entirely before the new span
+                spans[len] = (first, last);
+                len += 1;
+            } else if end.saturating_add(1) < first {
+                // Entirely after it
+                if !placed {
+                    spans[len] = (start, end);
+                    len += 1;
+                    placed = true;
+                }
+                spans[len] = (first, last);
+                len += 1;
+            } else {
+                // Overlapping or adjacent: absorb into the new span
+                start = start.min(first);
+                end = end.max(last);
             }
-            _ => unreachable!("Invalid dirty region state"),
         }
+        if !placed {
+            spans[len] = (start, end);
+            len += 1;
+        }
+
+        if len > MAX_DIRTY_SPANS {
+            // Out of room: close the smallest gap
+            let i = (0..len - 1)
+                .min_by_key(|&i| spans[i + 1].0 - spans[i].1)
+                .unwrap();
+            spans[i].1 = spans[i + 1].1;
+            spans.copy_within(i + 2..len, i + 1);
+            len -= 1;
+        }
+
+        self.spans[..len].copy_from_slice(&spans[..len]);
+        self.len = len as u8;
     }
 
     /// Check if the region is dirty
     pub fn is_dirty(&self) -> bool {
-        self.first_changed.is_some()
+        self.len > 0
     }
 
     /// Get the range of changed columns (inclusive), if any
+    ///
+    /// Covers every span, including the clean columns between them.
     pub fn range(&self) -> Option<(u16, u16)> {
-        match (self.first_changed, self.last_changed) {
-            (Some(first), Some(last)) => Some((first, last)),
-            _ => None,
+        match self.spans() {
+            [] => None,
+            [first, .., last] => Some((first.0, last.1)),
+            [only] => Some(*only),
         }
     }
+
+    /// The dirty spans (inclusive), in column order
+    pub fn spans(&self) -> &[(u16, u16)] {
+        &self.spans[..self.len as usize]
+    }
+}
+
+/// A cell type the line diff and hash work on
+pub trait LineCell: PartialEq + Sized {
+    /// Hash of a non-empty line
+    fn hash_cells(cells: &[Self]) -> u64;
+
+    /// First and last difference between two non-empty lines of equal length
+    fn diff_cells(old_line: &[Self], new_line: &[Self]) -> Option<(usize, usize)> {
+        scalar_line_diff(old_line, new_line)
+    }
+}
+
+impl LineCell for Cell {
+    /// FNV-1a over the character, attributes, colors and hyperlink of each cell
+    fn hash_cells(cells: &[Self]) -> u64 {
+        cells.iter().fold(FNV_OFFSET_BASIS, hash_cell)
+    }
+}
+
+impl LineCell for PackedCell {
+    /// Each cell is one word, hashed with the vectorized line hash
+    fn hash_cells(cells: &[Self]) -> u64 {
+        crate::simd::hash_words(PackedCell::as_words(cells))
+    }
+
+    /// Compares whole words, several at a time
+    fn diff_cells(old_line: &[Self], new_line: &[Self]) -> Option<(usize, usize)> {
+        crate::simd::find_diff(
+            PackedCell::as_words(old_line),
+            PackedCell::as_words(new_line),
+        )
+    }
 }
 
 /// Find the first and last difference in a line
 ///
-/// Optimized with early exit and chunk-based comparison for better performance.
-pub fn find_line_diff(old_line: &[Cell], new_line: &[Cell]) -> Option<(usize, usize)> {
+/// Screen lines ([`PackedCell`]) are compared with SIMD where available.
+pub fn find_line_diff<T: LineCell>(old_line: &[T], new_line: &[T]) -> Option<(usize, usize)> {
     let len = old_line.len();
 
     if len != new_line.len() {
@@ -84,6 +177,13 @@ pub fn find_line_diff(old_line: &[Cell], new_line: &[Cell]) -> Option<(usize, us
         return None;
     }
 
+    T::diff_cells(old_line, new_line)
+}
+
+/// Cell-by-cell version of [`find_line_diff`], with early exit
+fn scalar_line_diff<T: PartialEq>(old_line: &[T], new_line: &[T]) -> Option<(usize, usize)> {
+    let len = old_line.len();
+
     // Fast path: check if lines are identical using memory comparison
     // This is much faster than cell-by-cell comparison for identical lines
     if old_line == new_line {
@@ -110,184 +210,326 @@ pub fn find_line_diff(old_line: &[Cell], new_line: &[Cell]) -> Option<(usize, us
     Some((first_diff, last_diff))
 }
 
-/// Compute hash for a line (used for line matching)
-///
-/// Optimized version using FNV-1a hash algorithm which is faster than
-/// polynomial rolling hash and provides good distribution.
-pub fn hash_line(cells: &[Cell]) -> u64 {
-    // Empty lines hash to 0 (for compatibility with blank line detection)
-    if cells.is_empty() {
-        return 0;
+/// FNV-1a constants
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Mix one cell into an FNV-1a hash
+fn hash_cell(mut hash: u64, cell: &Cell) -> u64 {
+    // Hash character (4 bytes)
+    let ch_bytes = (cell.ch as u32).to_ne_bytes();
+    for &byte in &ch_bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
     }
 
-    // FNV-1a constants
-    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
-    const FNV_PRIME: u64 = 0x100000001b3;
+    // Hash attributes (2 bytes)
+    let attr_bytes = cell.attr.bits().to_ne_bytes();
+    for &byte in &attr_bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
 
-    let mut hash = FNV_OFFSET_BASIS;
+    // Optimized: hash colors using discriminant+data approach (2-3x faster)
+    // Converts color to (type_byte, data_u32) to minimize branches
+    #[inline(always)]
+    fn hash_color(hash: &mut u64, color: Color) {
+        let (disc, data) = color.hash_bytes();
 
-    for cell in cells {
-        // Hash character (4 bytes)
-        let ch_bytes = (cell.ch as u32).to_ne_bytes();
-        for &byte in &ch_bytes {
-            hash ^= byte as u64;
-            hash = hash.wrapping_mul(FNV_PRIME);
-        }
+        // Hash discriminant
+        *hash ^= disc as u64;
+        *hash = hash.wrapping_mul(FNV_PRIME);
 
-        // Hash attributes (2 bytes)
-        let attr_bytes = cell.attr.bits().to_ne_bytes();
-        for &byte in &attr_bytes {
-            hash ^= byte as u64;
-            hash = hash.wrapping_mul(FNV_PRIME);
+        // Hash data (for Ansi256 and RGB colors)
+        if data != 0 {
+            // Unroll data bytes for better performance
+            *hash ^= (data & 0xFF) as u64;
+            *hash = hash.wrapping_mul(FNV_PRIME);
+            *hash ^= ((data >> 8) & 0xFF) as u64;
+            *hash = hash.wrapping_mul(FNV_PRIME);
+            *hash ^= ((data >> 16) & 0xFF) as u64;
+            *hash = hash.wrapping_mul(FNV_PRIME);
+            *hash ^= ((data >> 24) & 0xFF) as u64;
+            *hash = hash.wrapping_mul(FNV_PRIME);
         }
+    }
 
-        // Optimized: hash colors using discriminant+data approach (2-3x faster)
-        // Converts color to (type_byte, data_u32) to minimize branches
-        #[inline(always)]
-        fn hash_color(hash: &mut u64, color: Color) {
-            const FNV_PRIME: u64 = 0x100000001b3;
-
-            let (disc, data) = color.hash_bytes();
+    hash_color(&mut hash, cell.fg());
+    hash_color(&mut hash, cell.bg());
 
-            // Hash discriminant
-            *hash ^= disc as u64;
-            *hash = hash.wrapping_mul(FNV_PRIME);
+    // Hash hyperlink id (2 bytes)
+    for &byte in &cell.hyperlink.to_ne_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
 
-            // Hash data (for Ansi256 and RGB colors)
-            if data != 0 {
-                // Unroll data bytes for better performance
-                *hash ^= (data & 0xFF) as u64;
-                *hash = hash.wrapping_mul(FNV_PRIME);
-                *hash ^= ((data >> 8) & 0xFF) as u64;
-                *hash = hash.wrapping_mul(FNV_PRIME);
-                *hash ^= ((data >> 16) & 0xFF) as u64;
-                *hash = hash.wrapping_mul(FNV_PRIME);
-                *hash ^= ((data >> 24) & 0xFF) as u64;
-                *hash = hash.wrapping_mul(FNV_PRIME);
-            }
-        }
+    hash
+}
 
-        hash_color(&mut hash, cell.fg());
-        hash_color(&mut hash, cell.bg());
+/// Compute hash for a line (used for line matching)
+///
+/// Screen lines ([`PackedCell`]) use a vectorized hash where available,
+/// [`Cell`]s are hashed with FNV-1a.
+pub fn hash_line<T: LineCell>(cells: &[T]) -> u64 {
+    // Empty lines hash to 0 (for compatibility with blank line detection)
+    if cells.is_empty() {
+        return 0;
     }
 
-    hash
+    T::hash_cells(cells)
 }
 
-/// Detect scroll operations using hash-based line matching (Modified Heckel's Algorithm)
-/// Inspired by ncurses hashmap.c
-pub fn detect_scrolls(old_hashes: &[u64], new_hashes: &[u64]) -> Vec<ScrollOp> {
-    let old_len = old_hashes.len();
-    let new_len = new_hashes.len();
+/// Hash-count table entry: how often a line hash occurs on the old and new
+/// screens, and where first
+#[derive(Debug, Clone, Copy)]
+struct LineCounts {
+    old: u32,
+    new: u32,
+    old_first: usize,
+    new_first: usize,
+    /// Every copy on the new screen is its old counterpart moved by the
+    /// same amount
+    moved_together: bool,
+}
 
-    if old_len == 0 || new_len == 0 {
-        return vec![];
+impl LineCounts {
+    const NONE: LineCounts = LineCounts {
+        old: 0,
+        new: 0,
+        old_first: 0,
+        new_first: 0,
+        moved_together: true,
+    };
+}
+
+/// Scroll detection and planning, with scratch space kept between frames
+///
+/// Once its tables have grown to the screen size, planning a frame
+/// allocates nothing.
+#[derive(Debug, Default)]
+pub struct ScrollPlanner {
+    table: HashMap<u64, LineCounts>,
+    // new line index -> matching old line index
+    old_num: Vec<Option<usize>>,
+    hunks: Vec<ScrollOp>,
+    // Old line each terminal row shows, as planned scrolls are applied
+    rows: Vec<Option<usize>>,
+    scrolled: Vec<Option<usize>>,
+}
+
+impl ScrollPlanner {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // Build mapping: new_line_index -> old_line_index
-    let mut old_num: Vec<Option<usize>> = vec![None; new_len];
+    /// Find candidate scroll hunks, see [`detect_scrolls`]
+    pub fn detect(&mut self, old_hashes: &[u64], new_hashes: &[u64]) -> &[ScrollOp] {
+        let old_len = old_hashes.len();
+        let new_len = new_hashes.len();
+        self.hunks.clear();
 
-    // Step 1: Find unique matches (hash appears exactly once in both old and new)
-    for new_i in 0..new_len {
-        let hash = new_hashes[new_i];
-        if hash == 0 {
-            continue; // Skip blank lines
+        if old_len == 0 || new_len == 0 {
+            return &self.hunks;
         }
 
-        // Count occurrences in new
-        let new_count = new_hashes.iter().filter(|&&h| h == hash).count();
-        if new_count != 1 {
-            continue; // Not unique in new
+        // Build mapping: new_line_index -> old_line_index
+        let old_num = &mut self.old_num;
+        old_num.clear();
+        old_num.resize(new_len, None);
+
+        // Step 1: Hash-count table, with where each hash first occurs
+        let table = &mut self.table;
+        table.clear();
+        for (i, &hash) in old_hashes.iter().enumerate().filter(|(_, h)| **h != 0) {
+            let counts = table.entry(hash).or_insert(LineCounts::NONE);
+            if counts.old == 0 {
+                counts.old_first = i;
+            }
+            counts.old += 1;
         }
-
-        // Find in old
-        let old_matches: Vec<usize> = old_hashes
-            .iter()
-            .enumerate()
-            .filter(|(_, h)| **h == hash)
-            .map(|(i, _)| i)
-            .collect();
-
-        if old_matches.len() == 1 {
-            // Unique match found
-            old_num[new_i] = Some(old_matches[0]);
+        for (i, &hash) in new_hashes.iter().enumerate().filter(|(_, h)| **h != 0) {
+            let counts = table.entry(hash).or_insert(LineCounts::NONE);
+            if counts.new == 0 {
+                counts.new_first = i;
+            }
+            counts.new += 1;
         }
-    }
 
-    // Step 2: Grow matches forward and backward
-    // If line N matched and N+1 also matches, extend the hunk
-    for new_i in 0..new_len {
-        if let Some(old_i) = old_num[new_i] {
-            // Try to extend forward
-            let mut offset = 1;
-            while new_i + offset < new_len
-                && old_i + offset < old_len
-                && old_num[new_i + offset].is_none()
-                && new_hashes[new_i + offset] == old_hashes[old_i + offset]
-                && new_hashes[new_i + offset] != 0
-            {
-                old_num[new_i + offset] = Some(old_i + offset);
-                offset += 1;
+        // Step 2: Match lines that appear exactly once on both screens.
+        // Repeated lines match too if they appear as often on both and every
+        // copy moved by the same amount, like a block of identical log lines
+        // scrolling. With equal counts, that's the case when each new copy
+        // has a copy at the same offset on the old screen.
+        let shift_of = |counts: &LineCounts| counts.old_first as isize - counts.new_first as isize;
+        for (i, &hash) in new_hashes.iter().enumerate().filter(|(_, h)| **h != 0) {
+            let counts = table.get_mut(&hash).unwrap();
+            if counts.old != counts.new {
+                continue;
             }
-
-            // Try to extend backward
-            offset = 1;
-            while new_i >= offset
-                && old_i >= offset
-                && old_num[new_i - offset].is_none()
-                && new_hashes[new_i - offset] == old_hashes[old_i - offset]
-                && new_hashes[new_i - offset] != 0
-            {
-                old_num[new_i - offset] = Some(old_i - offset);
-                offset += 1;
+            let old_i = i.wrapping_add_signed(shift_of(counts));
+            if old_hashes.get(old_i) != Some(&hash) {
+                counts.moved_together = false;
+            }
+        }
+        for (i, &hash) in new_hashes.iter().enumerate().filter(|(_, h)| **h != 0) {
+            let counts = &table[&hash];
+            if counts.old == counts.new && counts.moved_together {
+                old_num[i] = Some(i.wrapping_add_signed(shift_of(counts)));
             }
         }
-    }
-
-    // Step 3: Find scroll hunks (contiguous regions with same shift)
-    let mut scrolls = Vec::new();
-    let mut i = 0;
 
-    while i < new_len {
-        if let Some(old_i) = old_num[i] {
-            let shift = old_i as isize - i as isize;
+        // Step 3: Grow matches forward and backward
+        // If line N matched and N+1 also matches, extend the hunk
+        for new_i in 0..new_len {
+            if let Some(old_i) = old_num[new_i] {
+                // Try to extend forward
+                let mut offset = 1;
+                while new_i + offset < new_len
+                    && old_i + offset < old_len
+                    && old_num[new_i + offset].is_none()
+                    && new_hashes[new_i + offset] == old_hashes[old_i + offset]
+                    && new_hashes[new_i + offset] != 0
+                {
+                    old_num[new_i + offset] = Some(old_i + offset);
+                    offset += 1;
+                }
 
-            // Find contiguous region with same shift
-            let start = i;
-            let mut end = i;
+                // Try to extend backward
+                offset = 1;
+                while new_i >= offset
+                    && old_i >= offset
+                    && old_num[new_i - offset].is_none()
+                    && new_hashes[new_i - offset] == old_hashes[old_i - offset]
+                    && new_hashes[new_i - offset] != 0
+                {
+                    old_num[new_i - offset] = Some(old_i - offset);
+                    offset += 1;
+                }
+            }
+        }
 
-            while end + 1 < new_len {
-                if let Some(next_old) = old_num[end + 1] {
-                    let next_shift = next_old as isize - (end + 1) as isize;
-                    if next_shift == shift {
-                        end += 1;
+        // Step 4: Find scroll hunks (contiguous regions with same shift)
+        let mut i = 0;
+
+        while i < new_len {
+            if let Some(old_i) = old_num[i] {
+                let shift = old_i as isize - i as isize;
+
+                // Find contiguous region with same shift
+                let start = i;
+                let mut end = i;
+
+                while end + 1 < new_len {
+                    if let Some(next_old) = old_num[end + 1] {
+                        let next_shift = next_old as isize - (end + 1) as isize;
+                        if next_shift == shift {
+                            end += 1;
+                        } else {
+                            break;
+                        }
                     } else {
                         break;
                     }
-                } else {
-                    break;
                 }
-            }
 
-            let size = end - start + 1;
+                let size = end - start + 1;
+
+                // Apply heuristics (from ncurses):
+                // - Minimum hunk size of 3 lines
+                // - Accept if efficient enough: size + min(size/8, 2) >= abs(shift)
+                // Lines that stayed put need no scrolling at all
+                let min_efficiency = size + (size / 8).min(2);
+                let shift_abs = shift.unsigned_abs();
+
+                if shift != 0 && size >= 3 && min_efficiency >= shift_abs {
+                    self.hunks.push(ScrollOp { start, size, shift });
+                }
+
+                i = end + 1;
+            } else {
+                i += 1;
+            }
+        }
 
-            // Apply heuristics (from ncurses):
-            // - Minimum hunk size of 3 lines
-            // - Accept if efficient enough: size + min(size/8, 2) >= abs(shift)
-            let min_efficiency = size + (size / 8).min(2);
-            let shift_abs = shift.unsigned_abs();
+        &self.hunks
+    }
+
+    /// Choose the scrolls that make a frame cheaper, in the order to send
+    /// them, replacing the contents of `plan`
+    ///
+    /// `line_cost(old, new)` estimates the bytes needed to turn a terminal
+    /// row showing old line `old` (`None`: an erased row) into new line
+    /// `new`, and `scroll_cost` is the bytes one scroll takes. Each hunk from
+    /// [`detect`](Self::detect) is simulated on the rows it touches, and kept
+    /// only if the repaint it saves outweighs the scroll. Like ncurses, hunks
+    /// moving up go first from the top, then hunks moving down from the
+    /// bottom, and a hunk whose lines an earlier scroll already moved or
+    /// erased is dropped.
+    pub fn plan(
+        &mut self,
+        old_hashes: &[u64],
+        new_hashes: &[u64],
+        mut line_cost: impl FnMut(Option<usize>, usize) -> usize,
+        scroll_cost: usize,
+        plan: &mut Vec<ScrollOp>,
+    ) {
+        plan.clear();
+        if old_hashes.len() != new_hashes.len() || self.detect(old_hashes, new_hashes).is_empty() {
+            return;
+        }
 
-            if size >= 3 && min_efficiency >= shift_abs {
-                scrolls.push(ScrollOp { start, size, shift });
+        let Self {
+            hunks,
+            rows,
+            scrolled,
+            ..
+        } = self;
+        rows.clear();
+        rows.extend((0..old_hashes.len()).map(Some));
+
+        let up = hunks.iter().filter(|hunk| hunk.shift > 0);
+        let down = hunks.iter().rev().filter(|hunk| hunk.shift < 0);
+        for hunk in up.chain(down) {
+            let source = hunk.start.wrapping_add_signed(hunk.shift);
+            if !(source..source + hunk.size).all(|row| rows[row] == Some(row)) {
+                continue;
             }
 
-            i = end + 1;
-        } else {
-            i += 1;
+            let (top, bottom) = hunk.region();
+            scrolled.clear();
+            scrolled.extend_from_slice(&rows[top..=bottom]);
+            let n = hunk.shift.unsigned_abs();
+            let erased = if hunk.shift > 0 {
+                scrolled.rotate_left(n);
+                scrolled.len() - n..scrolled.len()
+            } else {
+                scrolled.rotate_right(n);
+                0..n
+            };
+            scrolled[erased].fill(None);
+
+            let mut repaint = |shown: &[Option<usize>]| -> usize {
+                shown
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &old)| line_cost(old, top + i))
+                    .sum()
+            };
+            if repaint(scrolled) + scroll_cost < repaint(&rows[top..=bottom]) {
+                rows[top..=bottom].copy_from_slice(scrolled);
+                plan.push(hunk.clone());
+            }
         }
     }
+}
 
-    scrolls
+/// Detect scroll operations using hash-based line matching (Modified Heckel's Algorithm)
+/// Inspired by ncurses hashmap.c
+///
+/// Returns every candidate hunk; [`ScrollPlanner::plan`] picks the ones
+/// worth sending. Lines with hash 0 (unknown) never match.
+pub fn detect_scrolls(old_hashes: &[u64], new_hashes: &[u64]) -> Vec<ScrollOp> {
+    ScrollPlanner::new().detect(old_hashes, new_hashes).to_vec()
 }
 
 #[cfg(test)]
@@ -334,6 +576,37 @@ mod tests {
         assert_eq!(region.range(), Some((5, 25)));
     }
 
+    #[test]
+    fn test_dirty_region_disjoint_spans() {
+        let mut region = DirtyRegion::clean();
+        region.mark(70, 79);
+        region.mark(0, 3);
+        assert_eq!(region.spans(), &[(0, 3), (70, 79)]);
+        assert_eq!(region.range(), Some((0, 79)));
+
+        // Adjacent and overlapping spans join up
+        region.mark(4, 6);
+        region.mark(65, 71);
+        assert_eq!(region.spans(), &[(0, 6), (65, 79)]);
+
+        // One span bridging the others swallows them
+        region.mark(2, 70);
+        assert_eq!(region.spans(), &[(0, 79)]);
+    }
+
+    #[test]
+    fn test_dirty_region_merges_closest_when_full() {
+        let mut region = DirtyRegion::clean();
+        for start in [0, 20, 40, 60] {
+            region.mark(start, start + 1);
+        }
+        assert_eq!(region.spans().len(), MAX_DIRTY_SPANS);
+
+        // A fifth span joins its nearest neighbour
+        region.mark(44, 45);
+        assert_eq!(region.spans(), &[(0, 1), (20, 21), (40, 45), (60, 61)]);
+    }
+
     #[test]
     fn test_find_line_diff_identical() {
         let line1 = vec![Cell::new('A'), Cell::new('B'), Cell::new('C')];
@@ -431,6 +704,15 @@ mod tests {
         assert_ne!(hash_line(&line1), hash_line(&line2));
     }
 
+    #[test]
+    fn test_hash_line_different_hyperlinks() {
+        let line1 = vec![Cell::new('A')];
+        let mut line2 = vec![Cell::new('A')];
+        line2[0].set_hyperlink(1);
+        assert_ne!(hash_line(&line1), hash_line(&line2));
+        assert_eq!(find_line_diff(&line1, &line2), Some((0, 0)));
+    }
+
     #[test]
     fn test_hash_line_empty() {
         let line1: Vec<Cell> = vec![];
@@ -530,16 +812,64 @@ mod tests {
 
     #[test]
     fn test_detect_scrolls_duplicate_hashes() {
-        // Duplicate hashes should not be matched (not unique)
+        // Both pairs of lines match, but each hunk is only 2 lines,
+        // below the minimum
         let old = vec![100, 100, 101, 102];
         let new = vec![101, 102, 100, 100];
         let scrolls = detect_scrolls(&old, &new);
 
-        // Only 101, 102 should match (unique hashes)
-        // But 2 lines is below minimum, so no scroll detected
         assert_eq!(scrolls.len(), 0);
     }
 
+    #[test]
+    fn test_detect_scrolls_repeated_lines() {
+        // A block of identical lines scrolled up by one
+        let old = vec![1, 7, 7, 7, 7, 2];
+        let new = vec![7, 7, 7, 7, 2, 3];
+        let scrolls = detect_scrolls(&old, &new);
+
+        assert_eq!(
+            scrolls,
+            vec![ScrollOp {
+                start: 0,
+                size: 5,
+                shift: 1
+            }]
+        );
+
+        // Copies that moved by different amounts aren't matched directly,
+        // but a hunk still grows over the one next to it
+        let old = vec![7, 1, 2, 3, 7, 9];
+        let new = vec![1, 2, 3, 7, 7, 9];
+        let scrolls = detect_scrolls(&old, &new);
+        assert_eq!(scrolls.len(), 1);
+        assert_eq!((scrolls[0].start, scrolls[0].size), (0, 4));
+    }
+
+    #[test]
+    fn test_detect_scrolls_skips_unmoved_lines() {
+        let old = vec![100, 101, 102, 103];
+        let new = old.clone();
+        assert!(detect_scrolls(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn test_scroll_op_region() {
+        let up = ScrollOp {
+            start: 0,
+            size: 5,
+            shift: 3,
+        };
+        assert_eq!(up.region(), (0, 7));
+
+        let down = ScrollOp {
+            start: 3,
+            size: 5,
+            shift: -3,
+        };
+        assert_eq!(down.region(), (0, 7));
+    }
+
     #[test]
     fn test_detect_scrolls_grow_matches() {
         // Should extend matches forward/backward
@@ -597,4 +927,93 @@ mod tests {
         assert_eq!(scrolls[1].size, 8);
         assert_eq!(scrolls[1].shift, -9);
     }
+
+    #[test]
+    fn test_hash_packed_line() {
+        use crate::style::{PackedCell, StyleId};
+
+        let line = |text: &str| -> Vec<PackedCell> {
+            text.chars()
+                .map(|ch| PackedCell::new(ch, StyleId::DEFAULT))
+                .collect()
+        };
+        let empty: Vec<PackedCell> = vec![];
+
+        assert_eq!(hash_line(&empty), 0);
+        assert_eq!(hash_line(&line("hello")), hash_line(&line("hello")));
+        assert_ne!(hash_line(&line("hello")), hash_line(&line("hellp")));
+        assert_ne!(hash_line(&line("ab")), hash_line(&line("ba")));
+        assert_eq!(find_line_diff(&line("hello"), &line("jello")), Some((0, 0)));
+    }
+
+    fn plan_scrolls(
+        old: &[u64],
+        new: &[u64],
+        line_cost: impl FnMut(Option<usize>, usize) -> usize,
+        scroll_cost: usize,
+    ) -> Vec<ScrollOp> {
+        let mut plan = Vec::new();
+        ScrollPlanner::new().plan(old, new, line_cost, scroll_cost, &mut plan);
+        plan
+    }
+
+    /// Cost of repainting a line: free if it's already there, 10 otherwise
+    fn hash_cost<'a>(
+        old: &'a [u64],
+        new: &'a [u64],
+    ) -> impl FnMut(Option<usize>, usize) -> usize + 'a {
+        move |old_i, new_i| match old_i {
+            Some(old_i) if old[old_i] == new[new_i] => 0,
+            _ => 10,
+        }
+    }
+
+    #[test]
+    fn test_plan_scrolls_weighs_cost() {
+        let old = vec![1, 2, 3, 100, 101, 102, 103, 104];
+        let new = vec![100, 101, 102, 103, 104, 4, 5, 6];
+
+        // Scrolling saves repainting 5 lines
+        let plan = plan_scrolls(&old, &new, hash_cost(&old, &new), 20);
+        assert_eq!(
+            plan,
+            vec![ScrollOp {
+                start: 0,
+                size: 5,
+                shift: 3
+            }]
+        );
+
+        // Not when the scroll costs more than the repaint
+        let plan = plan_scrolls(&old, &new, hash_cost(&old, &new), 60);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_plan_scrolls_drops_clobbered_hunks() {
+        // Swapping two blocks: the first scroll erases the lines the
+        // second one would move
+        let old = vec![
+            100, 101, 102, 103, 104, 105, 106, 107, 0, 200, 201, 202, 203, 204, 205, 206, 207,
+        ];
+        let new = vec![
+            200, 201, 202, 203, 204, 205, 206, 207, 0, 100, 101, 102, 103, 104, 105, 106, 107,
+        ];
+        assert_eq!(detect_scrolls(&old, &new).len(), 2);
+
+        let plan = plan_scrolls(&old, &new, hash_cost(&old, &new), 20);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].shift, 9);
+    }
+
+    #[test]
+    fn test_plan_scrolls_orders_hunks() {
+        // Upward hunks top-down, then downward hunks bottom-up
+        let old = vec![1, 10, 11, 12, 13, 2, 3, 20, 21, 22, 23, 4];
+        let new = vec![10, 11, 12, 13, 5, 6, 7, 8, 20, 21, 22, 23];
+        let plan = plan_scrolls(&old, &new, hash_cost(&old, &new), 5);
+
+        let shifts: Vec<isize> = plan.iter().map(|op| op.shift).collect();
+        assert_eq!(shifts, vec![1, -1]);
+    }
 }
@@ -0,0 +1,282 @@
+//! Encoding dirty lines into terminal output
+//!
+//! A [`LineEncoder`] turns the changed cells of a line into escape sequences
+//! and text, tracking what the terminal was last sent ([`EmitState`]) so
+//! only what differs goes out. The refresh loop runs one over the whole
+//! screen; parallel refresh runs one per chunk of lines, each into its own
+//! buffer, and stitches the results together in order.
+use crate::caps::Capabilities;
+use crate::color::Color;
+use crate::delta::DirtyRegion;
+use crate::error::Result;
+use crate::stats::FrameStats;
+use crate::style::{CellStyle, PackedCell, StyleId, StyleTable};
+use crate::writer::push_csi;
+
+/// Terminal state left by the output so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EmitState {
+    /// Where the cursor is (None = unknown, e.g. pending wrap)
+    pub cursor: Option<(u16, u16)>,
+    /// Style the last SGR sequence set
+    pub style: StyleId,
+    /// Whether something else may have changed the style since
+    pub style_unknown: bool,
+    /// Hyperlink currently open (0 = none)
+    pub hyperlink: u16,
+}
+
+impl EmitState {
+    /// Default style and no hyperlink, cursor position unknown
+    pub(crate) const UNKNOWN_CURSOR: EmitState = EmitState {
+        cursor: None,
+        style: StyleId::DEFAULT,
+        style_unknown: false,
+        hyperlink: 0,
+    };
+
+    /// Reset the terminal to the default style if the style is unknown
+    pub(crate) fn reset_unknown_style(&mut self, out: &mut String) {
+        if std::mem::take(&mut self.style_unknown) {
+            out.push_str("\x1b[0m");
+            self.style = StyleId::DEFAULT;
+        }
+    }
+}
+
+/// Style table access for an encoder
+pub(crate) enum Styles<'a> {
+    /// Owned by this encoder: SGR transitions are cached in the table
+    Cached(&'a mut StyleTable),
+    /// Shared between threads: transitions are worked out each time
+    Shared(&'a StyleTable),
+}
+
+impl Styles<'_> {
+    fn table(&self) -> &StyleTable {
+        match self {
+            Styles::Cached(table) => table,
+            Styles::Shared(table) => table,
+        }
+    }
+
+    fn get(&self, id: StyleId) -> &CellStyle {
+        self.table().get(id)
+    }
+
+    fn write_transition(&mut self, out: &mut String, from: StyleId, to: StyleId) {
+        match self {
+            Styles::Cached(table) => table.write_transition(out, from, to),
+            Styles::Shared(table) => {
+                if from != to {
+                    crate::sgr::write_transition(out, table.get(from).sgr(), table.get(to).sgr());
+                }
+            }
+        }
+    }
+}
+
+/// Emit the cheapest move of the terminal cursor to (y, x)
+///
+/// Cells of `row` between the cursor and the target may be re-printed
+/// instead, when they're drawn in the currently emitted style.
+pub(crate) fn move_cursor(
+    out: &mut String,
+    state: &mut EmitState,
+    styles: &StyleTable,
+    row: &[PackedCell],
+    y: usize,
+    x: usize,
+) {
+    let target = (y as u16, x as u16);
+    let reprint = match state.cursor {
+        Some((py, px)) if py == target.0 && px < target.1 && !state.style_unknown => {
+            let gap = &row[px as usize..x];
+            let emitted = styles.get(state.style).sgr();
+            gap.iter()
+                .all(|cell| {
+                    let style = styles.get(cell.style);
+                    style.sgr() == emitted
+                        && style.hyperlink == state.hyperlink
+                        && style.placeholder.is_none()
+                })
+                .then_some(gap)
+        }
+        _ => None,
+    };
+    crate::mvcur::mvcur(out, state.cursor, target, reprint);
+    state.cursor = Some(target);
+}
+
+/// Writes the changes of dirty lines to an output buffer
+pub(crate) struct LineEncoder<'a> {
+    pub out: &'a mut String,
+    pub state: &'a mut EmitState,
+    pub styles: Styles<'a>,
+    /// Interned hyperlink URIs: id N refers to hyperlinks[N - 1]
+    pub hyperlinks: &'a [String],
+    pub capabilities: &'a Capabilities,
+    pub stats: &'a mut FrameStats,
+}
+
+impl LineEncoder<'_> {
+    /// Send the changes within the dirty spans of line `y` and commit them
+    ///
+    /// Afterwards `current` matches what the terminal shows, the line is
+    /// clean, and its hash is `pending_hash` if nothing untouched was left
+    /// over.
+    pub(crate) fn encode_line(
+        &mut self,
+        y: usize,
+        current: &mut [PackedCell],
+        pending: &[PackedCell],
+        dirty: &mut DirtyRegion,
+        current_hash: &mut u64,
+        pending_hash: u64,
+    ) -> Result<()> {
+        for &(first_x, last_x) in dirty.spans() {
+            // Find actual differences within each dirty span, so the clean
+            // columns between spans are never compared or sent
+            let span = first_x as usize..=last_x as usize;
+            if let Some((first_diff, last_diff)) =
+                crate::delta::find_line_diff(&current[span.clone()], &pending[span])
+            {
+                let first = first_x as usize + first_diff;
+                let last = first_x as usize + last_diff;
+                self.emit_cells(y, current, pending, first, last)?;
+            }
+        }
+
+        // The line is fully emitted: commit it, so an aborted refresh leaves
+        // current matching what the terminal shows
+        for &(first_x, last_x) in dirty.spans() {
+            let span = first_x as usize..=last_x as usize;
+            current[span.clone()].copy_from_slice(&pending[span]);
+        }
+        // Untouched changes outside the spans weren't sent, so the line only
+        // has the pending hash if nothing is left over
+        *current_hash = if current == pending { pending_hash } else { 0 };
+        *dirty = DirtyRegion::clean();
+        self.stats.lines_diffed += 1;
+        Ok(())
+    }
+
+    /// Emit the changed cells of line `y` between columns `first` and `last`
+    fn emit_cells(
+        &mut self,
+        y: usize,
+        current: &mut [PackedCell],
+        pending: &[PackedCell],
+        first: usize,
+        last: usize,
+    ) -> Result<()> {
+        // Output changed cells, skipping over unchanged ones
+        let mut x = first;
+        while x <= last {
+            if pending[x] == current[x] {
+                x += 1;
+                continue;
+            }
+
+            if self.state.cursor != Some((y as u16, x as u16)) {
+                move_cursor(self.out, self.state, self.styles.table(), current, y, x);
+            }
+            let cell = pending[x];
+            let style = *self.styles.get(cell.style);
+
+            // Hyperlinks are switched with OSC 8, independently of SGR
+            if style.hyperlink != self.state.hyperlink {
+                self.state.hyperlink = style.hyperlink;
+                let uri = match style.hyperlink {
+                    0 => "",
+                    id => &self.hyperlinks[id as usize - 1],
+                };
+                crate::osc::write_hyperlink(self.out, uri);
+            }
+
+            // Emit only the style fields that changed
+            let sgr_start = self.out.len();
+            if std::mem::take(&mut self.state.style_unknown) {
+                crate::sgr::write_style(self.out, style.sgr());
+            } else {
+                self.styles
+                    .write_transition(self.out, self.state.style, cell.style);
+            }
+            if self.out.len() != sgr_start {
+                self.stats.style_switches += 1;
+            }
+            self.state.style = cell.style;
+
+            // Output character (with RLE optimization for runs of identical cells)
+            let run = pending[x..=last].iter().take_while(|c| **c == cell).count();
+
+            // Blanks can be erased instead of printed. Erased cells take
+            // the current background, which needs back color erase
+            let erasable = cell.ch == ' '
+                && style.attr.is_empty()
+                && style.hyperlink == 0
+                && (style.bg == Color::Reset || self.capabilities.back_color_erase);
+            if erasable {
+                if pending.len() - x > 3 && pending[x..].iter().all(|c| *c == cell) {
+                    // Blank up to the end of the line: EL
+                    self.out.push_str("\x1b[K");
+                    self.stats.cells_changed += pending.len() - x;
+                    // EL clears past the span too; those cells are blank
+                    // in pending, so they now match the terminal
+                    current[x..].fill(cell);
+                    x = last + 1;
+                    continue;
+                }
+                if run >= 8 {
+                    // Use ECH for long runs (leaves the cursor in place)
+                    push_csi(self.out, run as u32, 'X');
+                    self.stats.cells_changed += run;
+                    x += run;
+                    continue;
+                }
+            }
+
+            push_cell_text(self.out, cell.ch, &style);
+            let repeat = run - 1;
+            if self.capabilities.rep
+                && repeat > 0
+                && style.placeholder.is_none()
+                && 3 + repeat.ilog10() as usize + 1 < repeat * cell.ch.len_utf8()
+            {
+                // REP repeats the character just printed
+                push_csi(self.out, repeat as u32, 'b');
+                self.stats.cells_changed += run;
+                x += run;
+            } else {
+                self.stats.cells_changed += 1;
+                x += 1;
+            }
+            // Printing in the last column leaves a pending wrap, whose
+            // cursor position differs between terminals
+            self.state.cursor = if x < pending.len() {
+                Some((y as u16, x as u16))
+            } else {
+                None
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Write the text of a cell: its character, or for a Kitty image
+/// placeholder cell the placeholder followed by the row diacritic, the
+/// column diacritic (the cell's character) and the id diacritic
+fn push_cell_text(out: &mut String, ch: char, style: &CellStyle) {
+    let Some(placeholder) = style.placeholder else {
+        out.push(ch);
+        return;
+    };
+    out.push(crate::image::PLACEHOLDER);
+    out.extend(crate::image::placeholder_diacritic(placeholder.row));
+    out.push(ch);
+    if placeholder.id_msb != 0 {
+        out.extend(crate::image::placeholder_diacritic(
+            placeholder.id_msb as u16,
+        ));
+    }
+}
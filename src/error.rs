@@ -14,13 +14,19 @@ pub enum Error {
     /// Terminal is already initialized
     AlreadyInitialized,
     /// Invalid color pair ID
-    InvalidColorPair(u8),
+    InvalidColorPair(u16),
     /// Invalid coordinates
     InvalidCoordinates { y: u16, x: u16 },
     /// Invalid window dimensions
     InvalidDimensions { height: u16, width: u16 },
     /// Operation not supported on this platform
     NotSupported,
+    /// Every hyperlink id is used by cells on the screen
+    TooManyHyperlinks,
+    /// Image data has to be written out with `KittyImage::prepare` first
+    ImageNotPrepared,
+    /// No Kitty image with this id has been transmitted
+    ImageNotTransmitted(u32),
 }
 
 impl fmt::Display for Error {
@@ -37,6 +43,9 @@ impl fmt::Display for Error {
                 write!(f, "Invalid dimensions: {}x{}", height, width)
             }
             Error::NotSupported => write!(f, "Operation not supported"),
+            Error::TooManyHyperlinks => write!(f, "Too many hyperlinks on screen"),
+            Error::ImageNotPrepared => write!(f, "Image not prepared for transmission"),
+            Error::ImageNotTransmitted(id) => write!(f, "Kitty image {} not transmitted", id),
         }
     }
 }
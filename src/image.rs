@@ -7,7 +7,10 @@
 ///
 /// # Kitty
 /// Modern protocol with better performance and features
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /// Image transmission format
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -96,15 +99,45 @@ impl ImagePlacement {
     }
 }
 
+/// How the data of a Kitty image reaches the terminal
+///
+/// Everything but [`KittyMedium::Direct`] goes through the file system, so
+/// it needs the terminal to run on the same machine (not over SSH), but
+/// skips base64-encoding large images into the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KittyMedium {
+    /// Base64-encoded in the escape sequences (`t=d`)
+    Direct,
+    /// Read from a file already holding the image (`t=f`)
+    File(PathBuf),
+    /// Written to a temporary file, which the terminal deletes after
+    /// reading it (`t=t`), by [`KittyImage::prepare`]
+    TempFile,
+    /// Written to a POSIX shared memory object, which the terminal unlinks
+    /// after reading it (`t=s`), by [`KittyImage::prepare`]
+    SharedMemory,
+}
+
+/// Where [`KittyImage::prepare`] put the data for the terminal
+struct Written {
+    /// Path of the temporary file, or name of the shared memory object
+    location: Vec<u8>,
+    /// Bytes written
+    len: usize,
+}
+
 /// Kitty image protocol builder
 pub struct KittyImage<'a> {
     data: &'a [u8],
     format: ImageFormat,
+    medium: KittyMedium,
+    compress: bool,
     placement: ImagePlacement,
     image_id: Option<u32>,
     placement_id: Option<u32>,
     width_px: Option<u32>,
     height_px: Option<u32>,
+    written: Option<Written>,
 }
 
 impl<'a> KittyImage<'a> {
@@ -113,14 +146,37 @@ impl<'a> KittyImage<'a> {
         Self {
             data,
             format,
+            medium: KittyMedium::Direct,
+            compress: false,
             placement: ImagePlacement::default(),
             image_id: None,
             placement_id: None,
             width_px: None,
             height_px: None,
+            written: None,
         }
     }
 
+    /// Create a Kitty image the terminal reads from a file (`t=f`)
+    pub fn from_file(path: impl Into<PathBuf>, format: ImageFormat) -> KittyImage<'static> {
+        KittyImage::new(&[], format).with_medium(KittyMedium::File(path.into()))
+    }
+
+    /// Set how the data is transmitted (default: [`KittyMedium::Direct`])
+    pub fn with_medium(mut self, medium: KittyMedium) -> Self {
+        self.medium = medium;
+        self
+    }
+
+    /// Compress the data with zlib before transmitting it (`o=z`)
+    ///
+    /// Worth it for raw RGB/RGBA pixels; PNG data is compressed already.
+    /// Has no effect on [`KittyMedium::File`], whose file is sent as is.
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
     /// Set placement options
     pub fn placement(mut self, placement: ImagePlacement) -> Self {
         self.placement = placement;
@@ -146,16 +202,99 @@ impl<'a> KittyImage<'a> {
         self
     }
 
+    /// Write the data out for the terminal to pick up
+    ///
+    /// Needed once before generating sequences with
+    /// [`KittyMedium::TempFile`] and [`KittyMedium::SharedMemory`], and does
+    /// nothing for the other mediums. The terminal removes the file or
+    /// shared memory object after reading it, so the sequence has to be sent
+    /// exactly once; one that is never sent leaves the object behind.
+    pub fn prepare(mut self) -> crate::error::Result<Self> {
+        let shared_memory = match self.medium {
+            KittyMedium::TempFile => false,
+            KittyMedium::SharedMemory => true,
+            KittyMedium::Direct | KittyMedium::File(_) => return Ok(self),
+        };
+        if self.written.is_some() {
+            return Ok(self);
+        }
+        let data = self.payload_data();
+        let location = if shared_memory {
+            write_shared_memory(&data)?.into_bytes()
+        } else {
+            write_temp_file(&data)?
+                .into_os_string()
+                .into_encoded_bytes()
+        };
+        self.written = Some(Written {
+            location,
+            len: data.len(),
+        });
+        Ok(self)
+    }
+
     /// Generate the Kitty protocol escape sequence
-    pub fn to_sequence(&self) -> Result<String, std::fmt::Error> {
-        // Encode data as base64 first
-        let encoded = base64_encode(self.data);
+    ///
+    /// With [`KittyMedium::TempFile`] and [`KittyMedium::SharedMemory`] the
+    /// image has to be [prepared](Self::prepare) first.
+    pub fn to_sequence(&self) -> crate::error::Result<String> {
+        Ok(self.command(true, self.image_id)?.0)
+    }
+
+    /// Generate the escape sequence transmitting the image without
+    /// displaying it (`a=t`)
+    ///
+    /// The image is shown later by putting it with its id (see
+    /// [`put_kitty_image`]); the placement set on this builder is ignored.
+    /// Needs [`prepare`](Self::prepare) like [`to_sequence`](Self::to_sequence).
+    pub fn to_transmit_sequence(&self) -> crate::error::Result<String> {
+        Ok(self.command(false, self.image_id)?.0)
+    }
+
+    /// The image id set with [`with_image_id`](Self::with_image_id)
+    pub fn image_id(&self) -> Option<u32> {
+        self.image_id
+    }
+
+    /// The placement id set with [`with_placement_id`](Self::with_placement_id)
+    pub fn placement_id(&self) -> Option<u32> {
+        self.placement_id
+    }
+
+    /// The placement options
+    pub(crate) fn placement_options(&self) -> &ImagePlacement {
+        &self.placement
+    }
+
+    /// Whether the data is compressed before transmitting it
+    fn compressed(&self) -> bool {
+        self.compress && !matches!(self.medium, KittyMedium::File(_))
+    }
+
+    /// The data as transmitted, compressed if asked to
+    fn payload_data(&self) -> std::borrow::Cow<'a, [u8]> {
+        if self.compressed() {
+            miniz_oxide::deflate::compress_to_vec_zlib(self.data, 6).into()
+        } else {
+            self.data.into()
+        }
+    }
 
+    /// Build a transmit command (`a=t`), or a transmit and display one
+    /// (`a=T`) with the placement, for the image with id `image_id`
+    ///
+    /// Returns it with the number of bytes of image data the terminal gets:
+    /// after compression, or the size of the file it reads.
+    pub(crate) fn command(
+        &self,
+        display: bool,
+        image_id: Option<u32>,
+    ) -> crate::error::Result<(String, usize)> {
         // Build control data
         let mut control = String::new();
 
-        // Action: transmit and display
-        write!(control, "a=T")?;
+        // Action: transmit, and display unless the image is put later
+        write!(control, "a={}", if display { 'T' } else { 't' })?;
 
         // Format
         let format_code = match self.format {
@@ -167,8 +306,34 @@ impl<'a> KittyImage<'a> {
         };
         write!(control, ",f={}", format_code)?;
 
-        // Transmission medium: direct
-        write!(control, ",t=d")?;
+        // Transmission medium: the payload is the data itself, or where to
+        // find it
+        let (payload, sent) = match (&self.medium, &self.written) {
+            (KittyMedium::Direct, _) => {
+                write!(control, ",t=d")?;
+                let data = self.payload_data();
+                (base64_encode(&data), data.len())
+            }
+            (KittyMedium::File(path), _) => {
+                write!(control, ",t=f")?;
+                let len = std::fs::metadata(path).map_or(0, |meta| meta.len() as usize);
+                (base64_encode(path.as_os_str().as_encoded_bytes()), len)
+            }
+            (KittyMedium::TempFile, Some(written)) => {
+                write!(control, ",t=t")?;
+                (base64_encode(&written.location), written.len)
+            }
+            (KittyMedium::SharedMemory, Some(written)) => {
+                write!(control, ",t=s,S={}", written.len)?;
+                (base64_encode(&written.location), written.len)
+            }
+            (KittyMedium::TempFile | KittyMedium::SharedMemory, None) => {
+                return Err(crate::error::Error::ImageNotPrepared);
+            }
+        };
+        if self.compressed() {
+            write!(control, ",o=z")?;
+        }
 
         // Pixel dimensions (required for RGB/RGBA)
         if let Some(w) = self.width_px {
@@ -179,10 +344,19 @@ impl<'a> KittyImage<'a> {
         }
 
         // Image ID
-        if let Some(id) = self.image_id {
+        if let Some(id) = image_id {
             write!(control, ",i={}", id)?;
         }
 
+        if !display {
+            // Quiet: the reply to a command with an id would reach the
+            // input as keys
+            write!(control, ",q=2")?;
+            let mut output = String::new();
+            write_chunked(&mut output, &control, &payload)?;
+            return Ok((output, sent));
+        }
+
         // Placement ID
         if let Some(id) = self.placement_id {
             write!(control, ",p={}", id)?;
@@ -206,34 +380,132 @@ impl<'a> KittyImage<'a> {
         }
 
         let mut output = String::new();
+        write_chunked(&mut output, &control, &payload)?;
+        Ok((output, sent))
+    }
+}
 
-        // For small images, send in one chunk
-        if encoded.len() <= 4096 {
-            write!(output, "\x1b_G{};{}\x1b\\", control, encoded)?;
+/// Write a graphics command, splitting the payload into 4096-byte chunks
+/// linked with `m=1`
+fn write_chunked(output: &mut String, control: &str, payload: &str) -> std::fmt::Result {
+    // For small images, send in one chunk
+    if payload.len() <= 4096 {
+        return write!(output, "\x1b_G{};{}\x1b\\", control, payload);
+    }
+
+    // For large images, chunk the data
+    let chunks: Vec<&str> = payload
+        .as_bytes()
+        .chunks(4096)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        if i == 0 {
+            // First chunk - include control data and set m=1
+            write!(output, "\x1b_G{},m=1;{}\x1b\\", control, chunk)?;
+        } else if i == chunks.len() - 1 {
+            // Last chunk - m=0
+            write!(output, "\x1b_Gm=0;{}\x1b\\", chunk)?;
         } else {
-            // For large images, chunk the data
-            let chunks: Vec<&str> = encoded
-                .as_bytes()
-                .chunks(4096)
-                .map(|chunk| std::str::from_utf8(chunk).unwrap())
-                .collect();
-
-            for (i, chunk) in chunks.iter().enumerate() {
-                if i == 0 {
-                    // First chunk - include control data and set m=1
-                    write!(output, "\x1b_G{},m=1;{}\x1b\\", control, chunk)?;
-                } else if i == chunks.len() - 1 {
-                    // Last chunk - m=0
-                    write!(output, "\x1b_Gm=0;{}\x1b\\", chunk)?;
-                } else {
-                    // Middle chunk - m=1
-                    write!(output, "\x1b_Gm=1;{}\x1b\\", chunk)?;
-                }
-            }
+            // Middle chunk - m=1
+            write!(output, "\x1b_Gm=1;{}\x1b\\", chunk)?;
         }
+    }
+    Ok(())
+}
 
-        Ok(output)
+/// A name for the next temporary file or shared memory object, unique
+/// within the process
+fn object_name(prefix: &str) -> String {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{}-{}", prefix, std::process::id(), n)
+}
+
+/// Write image data to a new temporary file for `t=t`
+///
+/// Kitty only deletes files in a temporary directory whose name contains
+/// `tty-graphics-protocol`.
+fn write_temp_file(data: &[u8]) -> crate::error::Result<PathBuf> {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(object_name("tty-graphics-protocol-zaz"));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Readable by us (and the terminal, running as us) only
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    if let Err(e) = file.write_all(data) {
+        let _ = std::fs::remove_file(&path);
+        return Err(e.into());
+    }
+    Ok(path)
+}
+
+/// Write image data to a new POSIX shared memory object for `t=s`,
+/// returning its name
+#[cfg(unix)]
+fn write_shared_memory(data: &[u8]) -> crate::error::Result<String> {
+    // Kept short: macOS limits shared memory names to 31 bytes
+    let name = object_name("/zaz");
+    let c_name = std::ffi::CString::new(name.as_str()).expect("name has no NUL");
+
+    let fd = unsafe {
+        libc::shm_open(
+            c_name.as_ptr(),
+            libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+            0o600,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let result = fill_shared_memory(fd, data);
+    unsafe { libc::close(fd) };
+    if let Err(e) = result {
+        // The terminal won't be told about it, so nobody else unlinks it
+        unsafe { libc::shm_unlink(c_name.as_ptr()) };
+        return Err(e.into());
+    }
+    Ok(name)
+}
+
+/// Size a shared memory object and copy `data` into it
+#[cfg(unix)]
+fn fill_shared_memory(fd: libc::c_int, data: &[u8]) -> std::io::Result<()> {
+    if unsafe { libc::ftruncate(fd, data.len() as libc::off_t) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    // Shared memory objects can't be written to on every system, so map it
+    let map = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            data.len(),
+            libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        return Err(std::io::Error::last_os_error());
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), map as *mut u8, data.len());
+        libc::munmap(map, data.len());
     }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_shared_memory(_data: &[u8]) -> crate::error::Result<String> {
+    Err(crate::error::Error::NotSupported)
 }
 
 /// Sixel image encoder
@@ -358,7 +630,7 @@ fn match_color_to_palette(r: u8, g: u8, b: u8) -> usize {
 }
 
 /// Simple base64 encoding
-fn base64_encode(data: &[u8]) -> String {
+pub(crate) fn base64_encode(data: &[u8]) -> String {
     const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
     let mut result = String::new();
 
@@ -390,14 +662,429 @@ fn base64_encode(data: &[u8]) -> String {
     result
 }
 
-/// Delete a Kitty image by ID
-pub fn delete_kitty_image(image_id: u32) -> String {
-    format!("\x1b_Ga=d,d=I,i={}\x1b\\", image_id)
+/// Simple base64 decoding, `None` if the input is not valid base64
+pub(crate) fn base64_decode(data: &[u8]) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(data.len() / 4 * 3);
+
+    for chunk in data.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+
+        let mut bits = 0u32;
+        for &c in &chunk[..4 - padding] {
+            bits = (bits << 6) | value(c)?;
+        }
+        bits <<= 6 * padding as u32;
+
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        result.extend_from_slice(&bytes[..3 - padding]);
+    }
+
+    Some(result)
+}
+
+/// Which Kitty images or placements a delete command removes (`d=`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KittyDelete {
+    /// Every placement (`d=a`)
+    All,
+    /// Every placement of an image (`d=i`)
+    Image(u32),
+    /// One placement of an image (`d=i` with `p=`)
+    Placement { image_id: u32, placement_id: u32 },
+    /// Placements covering a cell, counted from 0 (`d=p`)
+    Cell { y: u16, x: u16 },
+    /// Placements on a z-index layer (`d=z`)
+    ZIndex(i32),
+}
+
+impl KittyDelete {
+    /// Generate the delete command
+    ///
+    /// With `free`, images left without placements also have their data
+    /// dropped by the terminal (the upper case `d=` variants); otherwise
+    /// they stay transmitted and can be put again.
+    pub fn to_sequence(self, free: bool) -> String {
+        let kind = |c: char| if free { c.to_ascii_uppercase() } else { c };
+        match self {
+            KittyDelete::All => format!("\x1b_Ga=d,d={}\x1b\\", kind('a')),
+            KittyDelete::Image(id) => format!("\x1b_Ga=d,d={},i={}\x1b\\", kind('i'), id),
+            KittyDelete::Placement {
+                image_id,
+                placement_id,
+            } => format!(
+                "\x1b_Ga=d,d={},i={},p={}\x1b\\",
+                kind('i'),
+                image_id,
+                placement_id
+            ),
+            KittyDelete::Cell { y, x } => format!(
+                "\x1b_Ga=d,d={},x={},y={}\x1b\\",
+                kind('p'),
+                x as u32 + 1,
+                y as u32 + 1
+            ),
+            KittyDelete::ZIndex(z) => format!("\x1b_Ga=d,d={},z={}\x1b\\", kind('z'), z),
+        }
+    }
+}
+
+/// Display a transmitted Kitty image (`a=p`)
+///
+/// The image goes where the terminal cursor is, which stays put (`C=1`);
+/// only the size and z-index of `placement` are used. The terminal doesn't
+/// reply (`q=2`).
+pub fn put_kitty_image(image_id: u32, placement_id: u32, placement: &ImagePlacement) -> String {
+    let mut seq = format!("\x1b_Ga=p,i={},p={}", image_id, placement_id);
+    if let Some(w) = placement.width {
+        let _ = write!(seq, ",c={}", w);
+    }
+    if let Some(h) = placement.height {
+        let _ = write!(seq, ",r={}", h);
+    }
+    if let Some(z) = placement.z_index {
+        let _ = write!(seq, ",z={}", z);
+    }
+    seq.push_str(",C=1,q=2\x1b\\");
+    seq
 }
 
-/// Delete all Kitty images
-pub fn delete_all_kitty_images() -> String {
-    "\x1b_Ga=d,d=A\x1b\\".to_string()
+/// Character standing for an image cell in a Kitty Unicode placeholder
+/// placement (`U=1`)
+///
+/// Its foreground color holds the image id, and combining diacritics after
+/// it the row and column of the image the cell shows.
+pub const PLACEHOLDER: char = '\u{10EEEE}';
+
+/// Diacritics numbering placeholder rows and columns, as ranges of code
+/// points: the Nth diacritic stands for N
+const PLACEHOLDER_DIACRITICS: &[(u32, u32)] = &[
+    (0x0305, 0x0305),
+    (0x030D, 0x030E),
+    (0x0310, 0x0310),
+    (0x0312, 0x0312),
+    (0x033D, 0x033F),
+    (0x0346, 0x0346),
+    (0x034A, 0x034C),
+    (0x0350, 0x0352),
+    (0x0357, 0x0357),
+    (0x035B, 0x035B),
+    (0x0363, 0x036F),
+    (0x0483, 0x0487),
+    (0x0592, 0x0595),
+    (0x0597, 0x0599),
+    (0x059C, 0x05A1),
+    (0x05A8, 0x05A9),
+    (0x05AB, 0x05AC),
+    (0x05AF, 0x05AF),
+    (0x05C4, 0x05C4),
+    (0x0610, 0x0617),
+    (0x0657, 0x065B),
+    (0x065D, 0x065E),
+    (0x06D6, 0x06DC),
+    (0x06DF, 0x06E2),
+    (0x06E4, 0x06E4),
+    (0x06E7, 0x06E8),
+    (0x06EB, 0x06EC),
+    (0x0730, 0x0730),
+    (0x0732, 0x0733),
+    (0x0735, 0x0736),
+    (0x073A, 0x073A),
+    (0x073D, 0x073D),
+    (0x073F, 0x0741),
+    (0x0743, 0x0743),
+    (0x0745, 0x0745),
+    (0x0747, 0x0747),
+    (0x0749, 0x074A),
+    (0x07EB, 0x07F1),
+    (0x07F3, 0x07F3),
+    (0x0816, 0x0819),
+    (0x081B, 0x0823),
+    (0x0825, 0x0827),
+    (0x0829, 0x082D),
+    (0x0951, 0x0951),
+    (0x0953, 0x0954),
+    (0x0F82, 0x0F83),
+    (0x0F86, 0x0F87),
+    (0x135D, 0x135F),
+    (0x17DD, 0x17DD),
+    (0x193A, 0x193A),
+    (0x1A17, 0x1A17),
+    (0x1A75, 0x1A7C),
+    (0x1B6B, 0x1B6B),
+    (0x1B6D, 0x1B73),
+    (0x1CD0, 0x1CD2),
+    (0x1CDA, 0x1CDB),
+    (0x1CE0, 0x1CE0),
+    (0x1DC0, 0x1DC1),
+    (0x1DC3, 0x1DC9),
+    (0x1DCB, 0x1DCC),
+    (0x1DD1, 0x1DE6),
+    (0x1DFE, 0x1DFE),
+    (0x20D0, 0x20D1),
+    (0x20D4, 0x20D7),
+    (0x20DB, 0x20DC),
+    (0x20E1, 0x20E1),
+    (0x20E7, 0x20E7),
+    (0x20E9, 0x20E9),
+    (0x20F0, 0x20F0),
+    (0x2CEF, 0x2CF1),
+    (0x2DE0, 0x2DFF),
+    (0xA66F, 0xA66F),
+    (0xA67C, 0xA67D),
+    (0xA6F0, 0xA6F1),
+    (0xA8E0, 0xA8F1),
+    (0xAAB0, 0xAAB0),
+    (0xAAB2, 0xAAB3),
+    (0xAAB7, 0xAAB8),
+    (0xAABE, 0xAABF),
+    (0xAAC1, 0xAAC1),
+    (0xFE20, 0xFE26),
+    (0x10A0F, 0x10A0F),
+    (0x10A38, 0x10A38),
+    (0x1D185, 0x1D189),
+    (0x1D1AA, 0x1D1AD),
+    (0x1D242, 0x1D244),
+];
+
+/// Most rows or columns a placeholder placement can number
+pub const MAX_PLACEHOLDER_CELLS: u16 = 297;
+
+/// The diacritic numbering row or column `n` of a placeholder placement
+pub(crate) fn placeholder_diacritic(n: u16) -> Option<char> {
+    let mut n = n as u32;
+    for &(first, last) in PLACEHOLDER_DIACRITICS {
+        if n <= last - first {
+            return char::from_u32(first + n);
+        }
+        n -= last - first + 1;
+    }
+    None
+}
+
+/// Foreground color of the placeholder cells of an image: the low 24 bits
+/// of its id
+pub(crate) fn placeholder_color(image_id: u32) -> crate::color::Color {
+    let [_, r, g, b] = image_id.to_be_bytes();
+    crate::color::Color::Rgb(r, g, b)
+}
+
+/// Text of the placeholder cells for columns `columns` of row `row` of an
+/// image, to print in its [`placeholder_color`]
+pub(crate) fn placeholder_text(image_id: u32, row: u16, columns: std::ops::Range<u16>) -> String {
+    let id_msb = (image_id >> 24) as u16;
+    let mut text = String::new();
+    for column in columns {
+        text.push(PLACEHOLDER);
+        text.extend(placeholder_diacritic(row));
+        text.extend(placeholder_diacritic(column));
+        if id_msb != 0 {
+            text.extend(placeholder_diacritic(id_msb));
+        }
+    }
+    text
+}
+
+/// Create a virtual placement (`U=1`), shown wherever placeholder cells of
+/// the image are printed
+///
+/// The image is fit into `rows` x `columns` cells.
+pub fn put_virtual_kitty_image(
+    image_id: u32,
+    placement_id: u32,
+    rows: u16,
+    columns: u16,
+) -> String {
+    format!(
+        "\x1b_Ga=p,U=1,i={},p={},c={},r={},q=2\x1b\\",
+        image_id, placement_id, columns, rows
+    )
+}
+
+/// Wrap graphics commands for tmux, which passes them on to the terminal
+/// it runs in (with `allow-passthrough` on)
+pub(crate) fn write_tmux_passthrough(out: &mut String, seq: &str) {
+    for command in seq.split_inclusive("\x1b\\") {
+        out.push_str("\x1bPtmux;");
+        for ch in command.chars() {
+            // ESC is doubled inside the passthrough
+            if ch == '\x1b' {
+                out.push('\x1b');
+            }
+            out.push(ch);
+        }
+        out.push_str("\x1b\\");
+    }
+}
+
+/// A placement of a Kitty image, as put by the screen
+#[derive(Debug, Clone)]
+pub struct KittyPlacement {
+    /// Placement id (0 = none given)
+    pub id: u32,
+    /// Position, size and z-index
+    pub placement: ImagePlacement,
+    /// Virtual placement (`U=1`), shown through placeholder cells instead
+    /// of at a position
+    pub placeholder: bool,
+}
+
+impl KittyPlacement {
+    /// Whether the placement covers cell (y, x)
+    ///
+    /// Without a size in cells, the placement is taken to cover one cell.
+    fn covers(&self, y: u16, x: u16) -> bool {
+        let (Some(left), Some(top)) = (self.placement.x, self.placement.y) else {
+            return false;
+        };
+        let width = self.placement.width.unwrap_or(1).max(1);
+        let height = self.placement.height.unwrap_or(1).max(1);
+        (left..left.saturating_add(width)).contains(&x)
+            && (top..top.saturating_add(height)).contains(&y)
+    }
+}
+
+/// A transmitted Kitty image
+#[derive(Debug, Default)]
+struct KittyEntry {
+    /// Bytes of image data sent
+    bytes: usize,
+    placements: Vec<KittyPlacement>,
+    next_placement_id: u32,
+}
+
+/// Kitty images transmitted to the terminal, and where they're placed
+///
+/// Kept by the screen as it sends graphics commands, so images can be put
+/// again without sending their data twice.
+#[derive(Debug, Default)]
+pub struct KittyRegistry {
+    images: HashMap<u32, KittyEntry>,
+    next_image_id: u32,
+}
+
+impl KittyRegistry {
+    /// Whether the image with this id has been transmitted
+    pub fn contains(&self, image_id: u32) -> bool {
+        self.images.contains_key(&image_id)
+    }
+
+    /// Ids of the transmitted images, in no particular order
+    pub fn image_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.images.keys().copied()
+    }
+
+    /// Placements of an image (empty for unknown images)
+    pub fn placements(&self, image_id: u32) -> &[KittyPlacement] {
+        self.images
+            .get(&image_id)
+            .map_or(&[], |entry| &entry.placements)
+    }
+
+    /// Bytes of image data held by the terminal for the transmitted images
+    pub fn uploaded_bytes(&self) -> usize {
+        self.images.values().map(|entry| entry.bytes).sum()
+    }
+
+    /// An image id not in use yet
+    pub(crate) fn allocate_image_id(&mut self) -> u32 {
+        loop {
+            self.next_image_id = self.next_image_id.wrapping_add(1).max(1);
+            if !self.contains(self.next_image_id) {
+                return self.next_image_id;
+            }
+        }
+    }
+
+    /// Record an image as transmitted, replacing its data if it already was
+    pub(crate) fn transmitted(&mut self, image_id: u32, bytes: usize) {
+        self.images.entry(image_id).or_default().bytes = bytes;
+    }
+
+    /// Record a placement of a transmitted image, allocating its id if it
+    /// has none
+    ///
+    /// Putting a placement id again moves that placement, as the terminal
+    /// does; id 0 stands for the one placement an image displayed without
+    /// a placement id has. Returns the placement id.
+    pub(crate) fn placed(
+        &mut self,
+        image_id: u32,
+        id: Option<u32>,
+        placement: ImagePlacement,
+        placeholder: bool,
+    ) -> u32 {
+        let entry = self.images.entry(image_id).or_default();
+        let id = id.unwrap_or_else(|| {
+            loop {
+                entry.next_placement_id = entry.next_placement_id.wrapping_add(1).max(1);
+                if !entry
+                    .placements
+                    .iter()
+                    .any(|p| p.id == entry.next_placement_id)
+                {
+                    break entry.next_placement_id;
+                }
+            }
+        });
+        entry.placements.retain(|p| p.id != id);
+        entry.placements.push(KittyPlacement {
+            id,
+            placement,
+            placeholder,
+        });
+        id
+    }
+
+    /// The virtual placement of an image, if it has one
+    pub(crate) fn placeholder_placement(&self, image_id: u32) -> Option<&KittyPlacement> {
+        self.placements(image_id).iter().find(|p| p.placeholder)
+    }
+
+    /// Apply a delete command
+    pub(crate) fn delete(&mut self, target: KittyDelete, free: bool) {
+        if let KittyDelete::Image(id) = target {
+            if free {
+                self.images.remove(&id);
+            } else if let Some(entry) = self.images.get_mut(&id) {
+                entry.placements.clear();
+            }
+            return;
+        }
+
+        self.images.retain(|&image_id, entry| {
+            let before = entry.placements.len();
+            entry.placements.retain(|p| match target {
+                KittyDelete::All => false,
+                KittyDelete::Placement {
+                    image_id: target_id,
+                    placement_id,
+                } => image_id != target_id || p.id != placement_id,
+                KittyDelete::Cell { y, x } => !p.covers(y, x),
+                KittyDelete::ZIndex(z) => p.placeholder || p.placement.z_index.unwrap_or(0) != z,
+                KittyDelete::Image(_) => unreachable!(),
+            });
+            // Freeing drops the images that just lost their last placement
+            !(free && before > 0 && entry.placements.is_empty())
+        });
+    }
 }
 
 #[cfg(test)]
@@ -444,6 +1131,26 @@ mod tests {
         assert_eq!(base64_encode(b"abc"), "YWJj");
     }
 
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode(b"aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64_decode(b"YQ==").unwrap(), b"a");
+        assert_eq!(base64_decode(b"YWI=").unwrap(), b"ab");
+        assert_eq!(base64_decode(b"YWJj").unwrap(), b"abc");
+        assert_eq!(base64_decode(b"").unwrap(), b"");
+        assert!(base64_decode(b"YWJ").is_none());
+        assert!(base64_decode(b"Y!==").is_none());
+    }
+
+    #[test]
+    fn test_base64_roundtrip() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(
+            base64_decode(base64_encode(&data).as_bytes()).unwrap(),
+            data
+        );
+    }
+
     #[test]
     fn test_kitty_image_simple() {
         let data = b"fake image data";
@@ -515,13 +1222,13 @@ mod tests {
 
     #[test]
     fn test_delete_kitty_image() {
-        let seq = delete_kitty_image(42);
+        let seq = KittyDelete::Image(42).to_sequence(true);
         assert_eq!(seq, "\x1b_Ga=d,d=I,i=42\x1b\\");
     }
 
     #[test]
     fn test_delete_all_kitty_images() {
-        let seq = delete_all_kitty_images();
+        let seq = KittyDelete::All.to_sequence(true);
         assert_eq!(seq, "\x1b_Ga=d,d=A\x1b\\");
     }
 
@@ -554,4 +1261,245 @@ mod tests {
         assert!(!seq.contains("m=1"));
         assert!(!seq.contains("m=0"));
     }
+
+    /// The payload of a single-chunk graphics command
+    fn payload(seq: &str) -> Vec<u8> {
+        let start = seq.find(';').unwrap() + 1;
+        let end = seq.len() - 2;
+        base64_decode(&seq.as_bytes()[start..end]).unwrap()
+    }
+
+    #[test]
+    fn test_kitty_compressed_direct() {
+        let data = vec![7u8; 3000];
+        let seq = KittyImage::new(&data, ImageFormat::Rgb)
+            .with_pixel_size(20, 50)
+            .with_compression(true)
+            .to_sequence()
+            .unwrap();
+
+        assert!(seq.starts_with("\x1b_Ga=T,f=24,t=d,o=z,s=20,v=50;"));
+        let inflated = miniz_oxide::inflate::decompress_to_vec_zlib(&payload(&seq)).unwrap();
+        assert_eq!(inflated, data);
+        assert!(seq.len() < 100);
+    }
+
+    #[test]
+    fn test_kitty_file_medium() {
+        let seq = KittyImage::from_file("/tmp/picture.png", ImageFormat::Png)
+            .with_compression(true)
+            .to_sequence()
+            .unwrap();
+
+        // The path is sent, and the file is never compressed
+        assert!(seq.starts_with("\x1b_Ga=T,f=100,t=f;"));
+        assert_eq!(payload(&seq), b"/tmp/picture.png");
+    }
+
+    #[test]
+    fn test_kitty_temp_file_medium() {
+        let data = b"temp file image";
+        let image = KittyImage::new(data, ImageFormat::Png).with_medium(KittyMedium::TempFile);
+        assert!(matches!(
+            image.to_sequence(),
+            Err(crate::error::Error::ImageNotPrepared)
+        ));
+        let image = image.prepare().unwrap();
+        let seq = image.to_sequence().unwrap();
+
+        assert!(seq.starts_with("\x1b_Ga=T,f=100,t=t;"));
+        // The file is written once, not on every call
+        assert_eq!(image.to_sequence().unwrap(), seq);
+        let path = String::from_utf8(payload(&seq)).unwrap();
+        assert!(path.contains("tty-graphics-protocol"));
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // Each image gets its own file
+        let other = KittyImage::new(data, ImageFormat::Png)
+            .with_medium(KittyMedium::TempFile)
+            .prepare()
+            .unwrap()
+            .to_sequence()
+            .unwrap();
+        let other_path = String::from_utf8(payload(&other)).unwrap();
+        assert_ne!(other_path, path);
+        std::fs::remove_file(&other_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_kitty_shared_memory_medium() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let seq = KittyImage::new(&data, ImageFormat::Rgba)
+            .with_pixel_size(25, 10)
+            .with_medium(KittyMedium::SharedMemory)
+            .prepare()
+            .unwrap()
+            .to_sequence()
+            .unwrap();
+
+        assert!(seq.starts_with("\x1b_Ga=T,f=32,t=s,S=1000,s=25,v=10;"));
+        let name = std::ffi::CString::new(payload(&seq)).unwrap();
+
+        // Read it back the way the terminal would, then unlink it
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+        assert!(fd >= 0);
+        let mut file = unsafe { <std::fs::File as std::os::fd::FromRawFd>::from_raw_fd(fd) };
+        let mut read = Vec::new();
+        std::io::Read::read_to_end(&mut file, &mut read).unwrap();
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn test_kitty_transmit_sequence() {
+        let seq = KittyImage::new(b"test", ImageFormat::Png)
+            .with_image_id(9)
+            .with_placement_id(2)
+            .placement(ImagePlacement::at(5, 10).with_size(20, 15))
+            .to_transmit_sequence()
+            .unwrap();
+
+        // Transmit only: no placement keys
+        assert_eq!(seq, "\x1b_Ga=t,f=100,t=d,i=9,q=2;dGVzdA==\x1b\\");
+    }
+
+    #[test]
+    fn test_put_kitty_image() {
+        let placement = ImagePlacement::at(5, 10).with_size(20, 15).with_z_index(-1);
+        assert_eq!(
+            put_kitty_image(9, 3, &placement),
+            "\x1b_Ga=p,i=9,p=3,c=20,r=15,z=-1,C=1,q=2\x1b\\"
+        );
+        assert_eq!(
+            put_kitty_image(9, 4, &ImagePlacement::default()),
+            "\x1b_Ga=p,i=9,p=4,C=1,q=2\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_kitty_delete_variants() {
+        assert_eq!(KittyDelete::All.to_sequence(false), "\x1b_Ga=d,d=a\x1b\\");
+        assert_eq!(
+            KittyDelete::Image(3).to_sequence(false),
+            "\x1b_Ga=d,d=i,i=3\x1b\\"
+        );
+        let placement = KittyDelete::Placement {
+            image_id: 3,
+            placement_id: 7,
+        };
+        assert_eq!(placement.to_sequence(true), "\x1b_Ga=d,d=I,i=3,p=7\x1b\\");
+        assert_eq!(
+            KittyDelete::Cell { y: 0, x: 4 }.to_sequence(false),
+            "\x1b_Ga=d,d=p,x=5,y=1\x1b\\"
+        );
+        assert_eq!(
+            KittyDelete::ZIndex(-2).to_sequence(true),
+            "\x1b_Ga=d,d=Z,z=-2\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_kitty_registry_placements() {
+        let mut registry = KittyRegistry::default();
+        let a = registry.allocate_image_id();
+        registry.transmitted(a, 100);
+        let b = registry.allocate_image_id();
+        registry.transmitted(b, 50);
+        assert_ne!(a, b);
+        assert_eq!(registry.uploaded_bytes(), 150);
+
+        let p1 = registry.placed(a, None, ImagePlacement::at(0, 0).with_size(4, 2), false);
+        let p2 = registry.placed(a, None, ImagePlacement::at(10, 0).with_z_index(1), false);
+        registry.placed(b, None, ImagePlacement::at(20, 5).with_size(2, 2), false);
+        assert_ne!(p1, p2);
+        assert_eq!(registry.placements(a).len(), 2);
+
+        // Putting a placement id again moves it
+        registry.placed(
+            a,
+            Some(p2),
+            ImagePlacement::at(12, 0).with_z_index(1),
+            false,
+        );
+        assert_eq!(registry.placements(a).len(), 2);
+        assert_eq!(registry.placements(a)[1].placement.x, Some(12));
+
+        // Keeping the data leaves the image to be put again
+        registry.delete(KittyDelete::Cell { y: 1, x: 3 }, false);
+        assert_eq!(registry.placements(a).len(), 1);
+        registry.delete(KittyDelete::ZIndex(1), false);
+        assert!(registry.placements(a).is_empty());
+        assert!(registry.contains(a));
+
+        // Freeing drops images without placements left
+        registry.delete(
+            KittyDelete::Placement {
+                image_id: b,
+                placement_id: 1,
+            },
+            true,
+        );
+        assert!(!registry.contains(b));
+        assert!(registry.contains(a));
+        registry.delete(KittyDelete::Image(a), true);
+        assert!(!registry.contains(a));
+        assert_eq!(registry.uploaded_bytes(), 0);
+    }
+
+    #[test]
+    fn test_placeholder_diacritics() {
+        assert_eq!(placeholder_diacritic(0), Some('\u{0305}'));
+        assert_eq!(placeholder_diacritic(1), Some('\u{030D}'));
+        assert_eq!(placeholder_diacritic(2), Some('\u{030E}'));
+        assert_eq!(placeholder_diacritic(3), Some('\u{0310}'));
+        assert_eq!(
+            placeholder_diacritic(MAX_PLACEHOLDER_CELLS - 1),
+            Some('\u{1D244}')
+        );
+        assert_eq!(placeholder_diacritic(MAX_PLACEHOLDER_CELLS), None);
+    }
+
+    #[test]
+    fn test_placeholder_text() {
+        assert_eq!(
+            placeholder_text(5, 1, 0..2),
+            "\u{10EEEE}\u{030D}\u{0305}\u{10EEEE}\u{030D}\u{030D}"
+        );
+        // Ids past 24 bits add the id diacritic
+        assert_eq!(
+            placeholder_text(0x0300_0001, 0, 2..3),
+            "\u{10EEEE}\u{0305}\u{030E}\u{0310}"
+        );
+        assert_eq!(
+            placeholder_color(0x0312_3456),
+            crate::color::Color::Rgb(0x12, 0x34, 0x56)
+        );
+    }
+
+    #[test]
+    fn test_put_virtual_kitty_image() {
+        assert_eq!(
+            put_virtual_kitty_image(7, 1, 4, 10),
+            "\x1b_Ga=p,U=1,i=7,p=1,c=10,r=4,q=2\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_tmux_passthrough() {
+        let mut out = String::new();
+        write_tmux_passthrough(&mut out, "\x1b_Gm=1;AAAA\x1b\\\x1b_Gm=0;BBBB\x1b\\");
+        assert_eq!(
+            out,
+            "\x1bPtmux;\x1b\x1b_Gm=1;AAAA\x1b\x1b\\\x1b\\\
+             \x1bPtmux;\x1b\x1b_Gm=0;BBBB\x1b\x1b\\\x1b\\"
+        );
+    }
 }
@@ -92,6 +92,208 @@ impl Key {
     }
 }
 
+/// A reply the terminal sends back in response to a query
+///
+/// Replies arrive on stdin interleaved with key presses, so they are parsed
+/// by the same input layer as keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    /// Primary device attributes: `CSI ? Ps ; ... c`
+    PrimaryAttributes(Vec<u16>),
+    /// Terminal name and version (XTVERSION): `DCS > | text ST`
+    Version(String),
+    /// DEC private mode report (DECRPM): `CSI ? mode ; status $ y`
+    Mode { mode: u16, status: u8 },
+    /// Current Kitty keyboard protocol flags: `CSI ? flags u`
+    KittyKeyboard(u32),
+    /// Kitty graphics protocol response: `APC G keys ; message ST`
+    KittyGraphics { id: u32, message: String },
+    /// Clipboard contents (OSC 52): `OSC 52 ; selection ; base64 ST`
+    Clipboard { selection: char, data: Vec<u8> },
+    /// Color report: `OSC 10/11 ; rgb:r/g/b ST` or `OSC 4 ; index ; rgb:r/g/b ST`
+    Color {
+        code: u16,
+        index: Option<u8>,
+        color: crate::color::Color,
+    },
+}
+
+impl Reply {
+    /// Parse every complete reply in `buf`, dropping other input
+    #[cfg(test)]
+    pub(crate) fn parse_all(buf: &[u8]) -> (Vec<Reply>, usize) {
+        Self::parse_all_keeping(buf, &mut Vec::new())
+    }
+
+    /// Parse every complete reply in `buf`
+    ///
+    /// Returns the replies together with the number of bytes consumed. A
+    /// sequence cut off at the end of the buffer is left unconsumed so the
+    /// caller can retry once more bytes arrive. Consumed bytes that are not
+    /// part of a recognized reply (keys typed meanwhile) are appended to
+    /// `input`.
+    pub(crate) fn parse_all_keeping(buf: &[u8], input: &mut Vec<u8>) -> (Vec<Reply>, usize) {
+        let mut replies = Vec::new();
+        let mut pos = 0;
+
+        while pos < buf.len() {
+            if buf[pos] != 27 {
+                input.push(buf[pos]);
+                pos += 1;
+                continue;
+            }
+
+            match Self::sequence_len(&buf[pos..]) {
+                Some(len) => {
+                    match Self::parse(&buf[pos..pos + len]) {
+                        Some(reply) => replies.push(reply),
+                        None => input.extend_from_slice(&buf[pos..pos + len]),
+                    }
+                    pos += len;
+                }
+                None => break, // Incomplete sequence, wait for more input
+            }
+        }
+
+        (replies, pos)
+    }
+
+    /// Length of the escape sequence at the start of `seq`, if complete
+    fn sequence_len(seq: &[u8]) -> Option<usize> {
+        match seq.get(1)? {
+            // CSI: parameters followed by a final byte in 0x40..=0x7E
+            b'[' => seq[2..]
+                .iter()
+                .position(|b| (0x40..=0x7e).contains(b))
+                .map(|i| i + 3),
+            // DCS, OSC and APC strings: terminated by ST (ESC \) or BEL
+            b'P' | b']' | b'_' => {
+                let mut i = 2;
+                while i < seq.len() {
+                    match seq[i] {
+                        7 => return Some(i + 1),
+                        27 if seq.get(i + 1) == Some(&b'\\') => return Some(i + 2),
+                        27 if i + 1 < seq.len() => return Some(i), // Malformed, drop it
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                None
+            }
+            _ => Some(2),
+        }
+    }
+
+    /// Parse a single complete escape sequence
+    fn parse(seq: &[u8]) -> Option<Reply> {
+        match seq[1] {
+            b'[' => Self::parse_csi(&seq[2..]),
+            b'P' => {
+                let body = Self::string_body(seq);
+                let text = body.strip_prefix(b">|")?;
+                Some(Reply::Version(String::from_utf8_lossy(text).into_owned()))
+            }
+            b']' => Self::parse_osc(Self::string_body(seq)),
+            b'_' => {
+                let body = Self::string_body(seq);
+                let body = std::str::from_utf8(body.strip_prefix(b"G")?).ok()?;
+                let (keys, message) = body.split_once(';').unwrap_or((body, ""));
+                let id = keys
+                    .split(',')
+                    .find_map(|kv| kv.strip_prefix("i="))
+                    .and_then(|id| id.parse().ok())
+                    .unwrap_or(0);
+                Some(Reply::KittyGraphics {
+                    id,
+                    message: message.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a CSI sequence (without the leading `ESC [`)
+    fn parse_csi(seq: &[u8]) -> Option<Reply> {
+        let (&final_byte, params) = seq.split_last()?;
+        let params = std::str::from_utf8(params.strip_prefix(b"?")?).ok()?;
+
+        match final_byte {
+            b'c' => {
+                let attrs = params.split(';').filter_map(|p| p.parse().ok()).collect();
+                Some(Reply::PrimaryAttributes(attrs))
+            }
+            b'y' => {
+                let (mode, status) = params.strip_suffix('$')?.split_once(';')?;
+                Some(Reply::Mode {
+                    mode: mode.parse().ok()?,
+                    status: status.parse().ok()?,
+                })
+            }
+            b'u' => Some(Reply::KittyKeyboard(params.parse().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// Parse an OSC reply (without introducer and terminator)
+    fn parse_osc(body: &[u8]) -> Option<Reply> {
+        let body = std::str::from_utf8(body).ok()?;
+        let (code, rest) = body.split_once(';')?;
+
+        match code {
+            "52" => {
+                let (selection, data) = rest.split_once(';')?;
+                Some(Reply::Clipboard {
+                    // Some terminals leave the selection out of the reply
+                    selection: selection.chars().next().unwrap_or('c'),
+                    data: crate::image::base64_decode(data.as_bytes())?,
+                })
+            }
+            "10" | "11" => Some(Reply::Color {
+                code: code.parse().ok()?,
+                index: None,
+                color: Self::parse_color_spec(rest)?,
+            }),
+            "4" => {
+                let (index, spec) = rest.split_once(';')?;
+                Some(Reply::Color {
+                    code: 4,
+                    index: Some(index.parse().ok()?),
+                    color: Self::parse_color_spec(spec)?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse an X11 color spec (`rgb:rrrr/gggg/bbbb`, 1 to 4 hex digits each)
+    fn parse_color_spec(spec: &str) -> Option<crate::color::Color> {
+        let mut channels = spec.strip_prefix("rgb:")?.split('/').map(|hex| {
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            // Scale to 8 bits: "f" and "ffff" both mean full intensity
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some((value * 255 / max) as u8)
+        });
+        let r = channels.next()??;
+        let g = channels.next()??;
+        let b = channels.next()??;
+        if channels.next().is_some() {
+            return None;
+        }
+        Some(crate::color::Color::Rgb(r, g, b))
+    }
+
+    /// Payload of a DCS/OSC/APC string, without introducer and terminator
+    fn string_body(seq: &[u8]) -> &[u8] {
+        let body = &seq[2..];
+        body.strip_suffix(b"\x1b\\")
+            .or_else(|| body.strip_suffix(b"\x07"))
+            .unwrap_or(body)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -290,4 +492,145 @@ mod tests {
             Some(Key::F(1))
         );
     }
+
+    #[test]
+    fn test_reply_primary_attributes() {
+        let (replies, consumed) = Reply::parse_all(b"\x1b[?62;4;22c");
+        assert_eq!(replies, vec![Reply::PrimaryAttributes(vec![62, 4, 22])]);
+        assert_eq!(consumed, 11);
+    }
+
+    #[test]
+    fn test_reply_version() {
+        let (replies, _) = Reply::parse_all(b"\x1bP>|kitty(0.31.0)\x1b\\");
+        assert_eq!(replies, vec![Reply::Version("kitty(0.31.0)".to_string())]);
+    }
+
+    #[test]
+    fn test_reply_mode_report() {
+        let (replies, _) = Reply::parse_all(b"\x1b[?2026;2$y\x1b[?2004;0$y");
+        assert_eq!(
+            replies,
+            vec![
+                Reply::Mode {
+                    mode: 2026,
+                    status: 2
+                },
+                Reply::Mode {
+                    mode: 2004,
+                    status: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reply_kitty_keyboard_and_graphics() {
+        let (replies, _) = Reply::parse_all(b"\x1b[?1u\x1b_Gi=31;OK\x1b\\");
+        assert_eq!(
+            replies,
+            vec![
+                Reply::KittyKeyboard(1),
+                Reply::KittyGraphics {
+                    id: 31,
+                    message: "OK".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reply_clipboard() {
+        // Both ST and BEL terminators are accepted
+        let (replies, _) = Reply::parse_all(b"\x1b]52;c;aGVsbG8=\x1b\\\x1b]52;p;d29ybGQ=\x07");
+        assert_eq!(
+            replies,
+            vec![
+                Reply::Clipboard {
+                    selection: 'c',
+                    data: b"hello".to_vec()
+                },
+                Reply::Clipboard {
+                    selection: 'p',
+                    data: b"world".to_vec()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reply_clipboard_invalid_base64() {
+        let (replies, consumed) = Reply::parse_all(b"\x1b]52;c;!!!\x1b\\");
+        assert!(replies.is_empty());
+        assert_eq!(consumed, 12);
+    }
+
+    #[test]
+    fn test_reply_color() {
+        let (replies, _) = Reply::parse_all(
+            b"\x1b]10;rgb:ffff/ffff/ffff\x1b\\\x1b]11;rgb:1e/1e/2e\x07\x1b]4;1;rgb:cdcd/0000/0000\x1b\\",
+        );
+        assert_eq!(
+            replies,
+            vec![
+                Reply::Color {
+                    code: 10,
+                    index: None,
+                    color: crate::color::Color::Rgb(255, 255, 255)
+                },
+                Reply::Color {
+                    code: 11,
+                    index: None,
+                    color: crate::color::Color::Rgb(0x1e, 0x1e, 0x2e)
+                },
+                Reply::Color {
+                    code: 4,
+                    index: Some(1),
+                    color: crate::color::Color::Rgb(0xcd, 0, 0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_color_spec() {
+        use crate::color::Color;
+        assert_eq!(
+            Reply::parse_color_spec("rgb:f/8/0"),
+            Some(Color::Rgb(255, 136, 0))
+        );
+        assert_eq!(
+            Reply::parse_color_spec("rgb:fff/000/800"),
+            Some(Color::Rgb(255, 0, 127))
+        );
+        assert_eq!(Reply::parse_color_spec("rgb:ff/ff"), None);
+        assert_eq!(Reply::parse_color_spec("rgb:ff/ff/ff/ff"), None);
+        assert_eq!(Reply::parse_color_spec("rgb:fffff/0/0"), None);
+        assert_eq!(Reply::parse_color_spec("#ffffff"), None);
+    }
+
+    #[test]
+    fn test_reply_incomplete_sequence_not_consumed() {
+        let (replies, consumed) = Reply::parse_all(b"\x1b[?1u\x1bP>|xterm(3");
+        assert_eq!(replies, vec![Reply::KittyKeyboard(1)]);
+        assert_eq!(consumed, 5);
+    }
+
+    #[test]
+    fn test_reply_skips_unrelated_input() {
+        // Key presses and unknown sequences mixed in with replies are dropped
+        let (replies, consumed) = Reply::parse_all(b"ab\x1b[A\x1b[?64c");
+        assert_eq!(replies, vec![Reply::PrimaryAttributes(vec![64])]);
+        assert_eq!(consumed, 11);
+    }
+
+    #[test]
+    fn test_reply_keeps_unrelated_input() {
+        let mut input = Vec::new();
+        let (replies, consumed) =
+            Reply::parse_all_keeping(b"ab\x1b[A\x1b[?64cq\x1b[?1", &mut input);
+        assert_eq!(replies, vec![Reply::PrimaryAttributes(vec![64])]);
+        assert_eq!(consumed, 12);
+        assert_eq!(input, b"ab\x1b[Aq");
+    }
 }
@@ -19,18 +19,29 @@
 mod acs;
 mod attr;
 mod backend;
+mod caps;
 mod cell;
 mod color;
+mod cursor;
 mod delta;
+mod encode;
 mod error;
 mod image;
 mod input;
 mod kitty;
 mod mosaic;
+mod mvcur;
+mod osc;
 mod panel;
 mod platform_io;
 mod screen;
+mod sgr;
+mod simd;
+mod stats;
+mod style;
+mod throttle;
 mod window;
+mod writer;
 
 pub mod ffi;
 
@@ -42,15 +53,23 @@ pub use acs::{
     AcsChar,
 };
 pub use attr::Attr;
+pub use caps::Capabilities;
 pub use cell::Cell;
-pub use color::{Color, ColorPair};
+pub use color::{Color, ColorPair, TerminalColors, Theme};
+pub use cursor::CursorStyle;
 pub use error::{Error, Result};
-pub use image::{ImageFormat, ImagePlacement, ImageProtocol, KittyImage, SixelImage};
+pub use image::{
+    ImageFormat, ImagePlacement, ImageProtocol, KittyDelete, KittyImage, KittyMedium,
+    KittyPlacement, KittyRegistry, MAX_PLACEHOLDER_CELLS, PLACEHOLDER, SixelImage,
+};
 pub use input::Key;
 pub use kitty::{KeyEvent, KeyEventType, KittyFlags, Modifiers};
 pub use mosaic::{MosaicConfig, SymbolSet, render_mosaic};
+pub use osc::ClipboardKind;
 pub use panel::Panel;
 pub use screen::Screen;
+pub use stats::{FrameStats, RenderStats};
+pub use throttle::RefreshPolicy;
 pub use window::Window;
 
 // Re-export internal modules for benchmarking purposes
@@ -58,6 +77,11 @@ pub use window::Window;
 pub mod __bench {
     pub use crate::cell::Cell;
     pub use crate::delta::{DirtyRegion, detect_scrolls, find_line_diff, hash_line};
+    pub use crate::mvcur::mvcur;
+    pub use crate::screen::{headless_screen, render_frame};
+    pub use crate::simd::scalar::{find_diff as scalar_find_diff, hash_words as scalar_hash_words};
+    pub use crate::simd::{find_diff, hash_words};
+    pub use crate::style::{PackedCell, StyleId};
 }
 
 // Re-export I/O functions for benchmarking
@@ -151,19 +151,19 @@ impl MosaicConfig {
 
 /// RGB color
 #[derive(Debug, Clone, Copy)]
-struct Rgb {
+pub(crate) struct Rgb {
     r: u8,
     g: u8,
     b: u8,
 }
 
 impl Rgb {
-    fn new(r: u8, g: u8, b: u8) -> Self {
+    pub(crate) fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
 
     /// Calculate luminance (perceived brightness)
-    fn luminance(&self) -> u8 {
+    pub(crate) fn luminance(&self) -> u8 {
         // Weighted RGB for human perception
         // Source: https://www.w3.org/TR/AERT/#color-contrast
         (self.r as f32 * 0.299 + self.g as f32 * 0.587 + self.b as f32 * 0.114) as u8
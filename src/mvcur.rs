@@ -0,0 +1,284 @@
+//! Cursor movement planning
+//!
+//! Like ncurses' `mvcur`, picks the cheapest byte sequence that takes the
+//! terminal cursor from one cell to another. Candidates are absolute moves
+//! (CUP, or VPA/HPA), relative moves (CUU/CUD/CUF/CUB, CR, LF, BS) and
+//! re-printing the cells between the two positions.
+//!
+//! LF is only used to move down: the backend runs the terminal in raw mode
+//! (no output post-processing), so LF keeps the column, and the target row
+//! is always on screen, so it never scrolls.
+use crate::style::PackedCell;
+use crate::writer::{push_csi, push_csi2};
+
+/// Vertical part of a relative move
+#[derive(Clone, Copy)]
+enum Vertical {
+    None,
+    LineFeed(u16),
+    Down(u16),
+    Up(u16),
+    Absolute(u16),
+}
+
+/// Horizontal part of a relative move
+#[derive(Clone, Copy)]
+enum Horizontal {
+    None,
+    Forward(u16),
+    Reprint,
+    Backspace(u16),
+    Back(u16),
+    CarriageReturn,
+    CarriageReturnForward(u16),
+    Absolute(u16),
+}
+
+/// Append the cheapest sequence moving the cursor from `from` to `to`
+///
+/// Positions are 0-based (row, col). `from` is `None` when the cursor
+/// position is unknown, which always yields CUP. `reprint` holds the cells
+/// from `from` up to (not including) `to` when the caller knows they can be
+/// printed again unchanged, i.e. same row and in the currently emitted style.
+pub fn mvcur(
+    out: &mut String,
+    from: Option<(u16, u16)>,
+    to: (u16, u16),
+    reprint: Option<&[PackedCell]>,
+) {
+    let Some((from_y, from_x)) = from else {
+        write_cup(out, to);
+        return;
+    };
+    if (from_y, from_x) == to {
+        return;
+    }
+    let (to_y, to_x) = to;
+
+    let vertical = cheapest(&vertical_moves(from_y, to_y));
+    let reprint = reprint.filter(|_| from_y == to_y && to_x > from_x);
+    let horizontal = cheapest(&horizontal_moves(from_x, to_x, reprint));
+
+    if cup_cost(to) <= vertical.0 + horizontal.0 {
+        write_cup(out, to);
+        return;
+    }
+
+    match vertical.1 {
+        Vertical::None => {}
+        Vertical::LineFeed(n) => (0..n).for_each(|_| out.push('\n')),
+        Vertical::Down(n) => write_csi_n(out, n, 'B'),
+        Vertical::Up(n) => write_csi_n(out, n, 'A'),
+        Vertical::Absolute(y) => push_csi(out, y as u32 + 1, 'd'),
+    }
+
+    match horizontal.1 {
+        Horizontal::None => {}
+        Horizontal::Forward(n) => write_csi_n(out, n, 'C'),
+        Horizontal::Reprint => reprint
+            .unwrap_or_default()
+            .iter()
+            .for_each(|cell| out.push(cell.ch)),
+        Horizontal::Backspace(n) => (0..n).for_each(|_| out.push('\x08')),
+        Horizontal::Back(n) => write_csi_n(out, n, 'D'),
+        Horizontal::CarriageReturn => out.push('\r'),
+        Horizontal::CarriageReturnForward(n) => {
+            out.push('\r');
+            write_csi_n(out, n, 'C');
+        }
+        Horizontal::Absolute(x) => push_csi(out, x as u32 + 1, 'G'),
+    }
+}
+
+/// Ways of getting from row `from` to row `to`, with their cost in bytes
+fn vertical_moves(from: u16, to: u16) -> [(usize, Vertical); 3] {
+    let absolute = (csi_param_cost(to + 1), Vertical::Absolute(to));
+    if to > from {
+        let n = to - from;
+        [
+            (n as usize, Vertical::LineFeed(n)),
+            (csi_n_cost(n), Vertical::Down(n)),
+            absolute,
+        ]
+    } else if to < from {
+        let n = from - to;
+        [(csi_n_cost(n), Vertical::Up(n)), absolute, absolute]
+    } else {
+        [(0, Vertical::None), absolute, absolute]
+    }
+}
+
+/// Ways of getting from column `from` to column `to`, with their cost in bytes
+fn horizontal_moves(
+    from: u16,
+    to: u16,
+    reprint: Option<&[PackedCell]>,
+) -> [(usize, Horizontal); 4] {
+    let absolute = (csi_param_cost(to + 1), Horizontal::Absolute(to));
+    let carriage_return = if to == 0 {
+        (1, Horizontal::CarriageReturn)
+    } else {
+        (1 + csi_n_cost(to), Horizontal::CarriageReturnForward(to))
+    };
+
+    if to > from {
+        let n = to - from;
+        let reprint = match reprint {
+            Some(cells) => (
+                cells.iter().map(|cell| cell.ch.len_utf8()).sum(),
+                Horizontal::Reprint,
+            ),
+            None => absolute,
+        };
+        [
+            (csi_n_cost(n), Horizontal::Forward(n)),
+            reprint,
+            carriage_return,
+            absolute,
+        ]
+    } else if to < from {
+        let n = from - to;
+        [
+            (n as usize, Horizontal::Backspace(n)),
+            (csi_n_cost(n), Horizontal::Back(n)),
+            carriage_return,
+            absolute,
+        ]
+    } else {
+        [(0, Horizontal::None), absolute, absolute, absolute]
+    }
+}
+
+/// Pick the cheapest candidate, the earliest one on ties
+fn cheapest<T: Copy>(candidates: &[(usize, T)]) -> (usize, T) {
+    let mut best = candidates[0];
+    for &candidate in &candidates[1..] {
+        if candidate.0 < best.0 {
+            best = candidate;
+        }
+    }
+    best
+}
+
+/// Number of decimal digits in `n`
+fn digits(n: u16) -> usize {
+    n.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+/// Cost of `CSI n <final>`, where a count of 1 is left out
+fn csi_n_cost(n: u16) -> usize {
+    if n == 1 { 3 } else { 3 + digits(n) }
+}
+
+/// Cost of `CSI param <final>`
+fn csi_param_cost(param: u16) -> usize {
+    3 + digits(param)
+}
+
+/// Cost of CUP, with the defaulted parameters left out
+fn cup_cost((y, x): (u16, u16)) -> usize {
+    match (y, x) {
+        (0, 0) => 3,
+        (y, 0) => 3 + digits(y + 1),
+        (y, x) => 4 + digits(y + 1) + digits(x + 1),
+    }
+}
+
+fn write_cup(out: &mut String, (y, x): (u16, u16)) {
+    match (y, x) {
+        (0, 0) => out.push_str("\x1b[H"),
+        (y, 0) => push_csi(out, y as u32 + 1, 'H'),
+        (y, x) => push_csi2(out, y as u32 + 1, x as u32 + 1, 'H'),
+    }
+}
+
+fn write_csi_n(out: &mut String, n: u16, final_byte: char) {
+    if n == 1 {
+        out.push_str("\x1b[");
+        out.push(final_byte);
+    } else {
+        push_csi(out, n as u32, final_byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::StyleId;
+
+    fn plan(from: Option<(u16, u16)>, to: (u16, u16), reprint: Option<&[PackedCell]>) -> String {
+        let mut out = String::new();
+        mvcur(&mut out, from, to, reprint);
+        out
+    }
+
+    #[test]
+    fn test_unknown_position_uses_cup() {
+        assert_eq!(plan(None, (5, 10), None), "\x1b[6;11H");
+        assert_eq!(plan(None, (5, 0), None), "\x1b[6H");
+        assert_eq!(plan(None, (0, 0), None), "\x1b[H");
+    }
+
+    #[test]
+    fn test_same_position_emits_nothing() {
+        assert_eq!(plan(Some((3, 4)), (3, 4), None), "");
+    }
+
+    #[test]
+    fn test_horizontal_moves() {
+        assert_eq!(plan(Some((5, 10)), (5, 11), None), "\x1b[C");
+        assert_eq!(plan(Some((5, 10)), (5, 30), None), "\x1b[20C");
+        assert_eq!(plan(Some((5, 10)), (5, 8), None), "\x08\x08");
+        assert_eq!(plan(Some((5, 10)), (5, 0), None), "\r");
+        assert_eq!(plan(Some((5, 70)), (5, 1), None), "\r\x1b[C");
+        assert_eq!(plan(Some((5, 70)), (5, 20), None), "\x1b[50D");
+        assert_eq!(plan(Some((5, 150)), (5, 5), None), "\x1b[6G");
+    }
+
+    #[test]
+    fn test_vertical_moves() {
+        assert_eq!(plan(Some((5, 10)), (7, 10), None), "\n\n");
+        assert_eq!(plan(Some((5, 10)), (12, 10), None), "\x1b[7B");
+        assert_eq!(plan(Some((5, 10)), (4, 10), None), "\x1b[A");
+    }
+
+    #[test]
+    fn test_next_line_start() {
+        // End of one line to the start of the next: CR LF beats CUP
+        assert_eq!(plan(Some((5, 40)), (6, 0), None), "\n\r");
+    }
+
+    #[test]
+    fn test_far_move_uses_cup() {
+        assert_eq!(plan(Some((20, 70)), (2, 3), None), "\x1b[3;4H");
+    }
+
+    #[test]
+    fn test_reprint_short_gap() {
+        let cells = [
+            PackedCell::new('a', StyleId::DEFAULT),
+            PackedCell::new('b', StyleId::DEFAULT),
+        ];
+        assert_eq!(plan(Some((0, 3)), (0, 5), Some(&cells)), "ab");
+
+        // A single cell is cheaper to reprint than CUF (3 bytes)
+        assert_eq!(plan(Some((0, 3)), (0, 4), Some(&cells[..1])), "a");
+    }
+
+    #[test]
+    fn test_reprint_ignored_across_rows() {
+        let cells = [PackedCell::new('a', StyleId::DEFAULT)];
+        assert_eq!(plan(Some((0, 3)), (1, 4), Some(&cells)), "\n\x1b[C");
+    }
+
+    #[test]
+    fn test_cost_helpers() {
+        assert_eq!(digits(0), 1);
+        assert_eq!(digits(9), 1);
+        assert_eq!(digits(10), 2);
+        assert_eq!(digits(1000), 4);
+        assert_eq!(cup_cost((5, 10)), "\x1b[6;11H".len());
+        assert_eq!(csi_n_cost(1), "\x1b[C".len());
+        assert_eq!(csi_n_cost(12), "\x1b[12C".len());
+    }
+}
@@ -0,0 +1,255 @@
+/// Operating System Command (OSC) sequences
+///
+/// OSC sequences control terminal features outside the character grid:
+/// hyperlinks, clipboard, window title and palette. All sequences are
+/// terminated with ST (`ESC \`).
+use crate::color::Color;
+use std::fmt::Write;
+
+/// Clipboard selection targeted by OSC 52
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardKind {
+    /// The system clipboard (what Ctrl-C/Ctrl-V use)
+    Clipboard,
+    /// The primary selection (X11 middle-click paste)
+    Primary,
+}
+
+impl ClipboardKind {
+    /// Selection parameter used in OSC 52
+    pub(crate) fn selection(&self) -> char {
+        match self {
+            ClipboardKind::Clipboard => 'c',
+            ClipboardKind::Primary => 'p',
+        }
+    }
+}
+
+/// Generate OSC 52 sequence that copies `text` to the clipboard
+pub(crate) fn set_clipboard_sequence(kind: ClipboardKind, text: &str) -> String {
+    format!(
+        "\x1b]52;{};{}\x1b\\",
+        kind.selection(),
+        crate::image::base64_encode(text.as_bytes())
+    )
+}
+
+/// Generate OSC 52 sequence that asks the terminal for the clipboard contents
+pub(crate) fn query_clipboard_sequence(kind: ClipboardKind) -> String {
+    format!("\x1b]52;{};?\x1b\\", kind.selection())
+}
+
+/// Generate OSC 10/11/4 queries for the default colors and the first
+/// `palette` palette entries
+pub(crate) fn query_colors_sequence(palette: u8) -> String {
+    let mut seq = String::from("\x1b]10;?\x1b\\\x1b]11;?\x1b\\");
+    for index in 0..palette {
+        write!(seq, "\x1b]4;{};?\x1b\\", index).unwrap();
+    }
+    seq
+}
+
+/// Generate OSC 12 sequence that sets the cursor color
+///
+/// [`Color::Reset`] restores the terminal's default cursor color (OSC 112).
+pub(crate) fn cursor_color_sequence(color: Color) -> String {
+    match color.to_rgb() {
+        Some(rgb) => format!("\x1b]12;{}\x1b\\", color_spec(rgb)),
+        None => "\x1b]112\x1b\\".to_string(),
+    }
+}
+
+/// Generate OSC 4 sequence that redefines a palette entry
+///
+/// [`Color::Reset`] restores the entry to the terminal's default (OSC 104).
+pub(crate) fn palette_color_sequence(index: u8, color: Color) -> String {
+    match color.to_rgb() {
+        Some(rgb) => format!("\x1b]4;{};{}\x1b\\", index, color_spec(rgb)),
+        None => format!("\x1b]104;{}\x1b\\", index),
+    }
+}
+
+/// Generate OSC 104 sequence that restores the whole palette
+pub(crate) fn reset_palette_sequence() -> &'static str {
+    "\x1b]104\x1b\\"
+}
+
+/// Format a color as an X11 color spec (`rgb:rr/gg/bb`)
+fn color_spec((r, g, b): (u8, u8, u8)) -> String {
+    format!("rgb:{:02x}/{:02x}/{:02x}", r, g, b)
+}
+
+/// Generate OSC 2 sequence that sets the window title
+pub(crate) fn set_title_sequence(title: &str) -> String {
+    format!("\x1b]2;{}\x1b\\", strip_controls(title))
+}
+
+/// Generate OSC 1 sequence that sets the icon name (tab title in most terminals)
+pub(crate) fn set_icon_name_sequence(name: &str) -> String {
+    format!("\x1b]1;{}\x1b\\", strip_controls(name))
+}
+
+/// Generate XTWINOPS sequence that saves the icon name and window title
+pub(crate) fn push_title_sequence() -> &'static str {
+    "\x1b[22;0t"
+}
+
+/// Generate XTWINOPS sequence that restores the saved icon name and window title
+pub(crate) fn pop_title_sequence() -> &'static str {
+    "\x1b[23;0t"
+}
+
+/// Generate OSC 7 sequence that reports the working directory as a file:// URL
+pub(crate) fn working_directory_sequence(host: &str, path: &str) -> String {
+    let mut url = String::with_capacity(path.len() + host.len() + 7);
+    url.push_str("file://");
+    url.push_str(&percent_encode(host));
+    for &byte in path.as_bytes() {
+        // Keep path separators and unreserved characters, escape the rest
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            write!(url, "%{:02X}", byte).unwrap();
+        }
+    }
+    format!("\x1b]7;{}\x1b\\", url)
+}
+
+/// Remove control characters, which would end or corrupt the sequence
+fn strip_controls(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Append the OSC 8 sequence that starts a hyperlink (empty URI ends it)
+pub(crate) fn write_hyperlink(out: &mut String, uri: &str) {
+    out.push_str("\x1b]8;;");
+    out.push_str(uri);
+    out.push_str("\x1b\\");
+}
+
+/// Percent-encode bytes that may not appear inside an OSC payload
+///
+/// Control characters, space and non-ASCII bytes are encoded so the result
+/// is always printable ASCII and can't terminate the sequence. Existing
+/// escapes are left alone, the input is expected to already be a URI.
+pub(crate) fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for &byte in text.as_bytes() {
+        if byte.is_ascii_graphic() {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{:02X}", byte).unwrap();
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hyperlink_sequence() {
+        let mut out = String::new();
+        write_hyperlink(&mut out, "https://example.com");
+        assert_eq!(out, "\x1b]8;;https://example.com\x1b\\");
+
+        out.clear();
+        write_hyperlink(&mut out, "");
+        assert_eq!(out, "\x1b]8;;\x1b\\");
+    }
+
+    #[test]
+    fn test_set_clipboard_sequence() {
+        assert_eq!(
+            set_clipboard_sequence(ClipboardKind::Clipboard, "hello"),
+            "\x1b]52;c;aGVsbG8=\x1b\\"
+        );
+        assert_eq!(
+            set_clipboard_sequence(ClipboardKind::Primary, ""),
+            "\x1b]52;p;\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_query_clipboard_sequence() {
+        assert_eq!(
+            query_clipboard_sequence(ClipboardKind::Clipboard),
+            "\x1b]52;c;?\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_query_colors_sequence() {
+        assert_eq!(
+            query_colors_sequence(2),
+            "\x1b]10;?\x1b\\\x1b]11;?\x1b\\\x1b]4;0;?\x1b\\\x1b]4;1;?\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_cursor_color_sequence() {
+        assert_eq!(
+            cursor_color_sequence(Color::Rgb(255, 128, 0)),
+            "\x1b]12;rgb:ff/80/00\x1b\\"
+        );
+        assert_eq!(
+            cursor_color_sequence(Color::Red),
+            "\x1b]12;rgb:cd/00/00\x1b\\"
+        );
+        assert_eq!(cursor_color_sequence(Color::Reset), "\x1b]112\x1b\\");
+    }
+
+    #[test]
+    fn test_palette_color_sequence() {
+        assert_eq!(
+            palette_color_sequence(1, Color::Rgb(0xff, 0x55, 0x55)),
+            "\x1b]4;1;rgb:ff/55/55\x1b\\"
+        );
+        assert_eq!(
+            palette_color_sequence(200, Color::Ansi256(196)),
+            "\x1b]4;200;rgb:ff/00/00\x1b\\"
+        );
+        assert_eq!(palette_color_sequence(3, Color::Reset), "\x1b]104;3\x1b\\");
+        assert_eq!(reset_palette_sequence(), "\x1b]104\x1b\\");
+    }
+
+    #[test]
+    fn test_title_sequences() {
+        assert_eq!(set_title_sequence("zaz"), "\x1b]2;zaz\x1b\\");
+        assert_eq!(set_icon_name_sequence("zaz"), "\x1b]1;zaz\x1b\\");
+        assert_eq!(push_title_sequence(), "\x1b[22;0t");
+        assert_eq!(pop_title_sequence(), "\x1b[23;0t");
+    }
+
+    #[test]
+    fn test_title_strips_controls() {
+        assert_eq!(
+            set_title_sequence("evil\x1b\\\x07title"),
+            "\x1b]2;evil\\title\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_working_directory_sequence() {
+        assert_eq!(
+            working_directory_sequence("host", "/home/me/my dir"),
+            "\x1b]7;file://host/home/me/my%20dir\x1b\\"
+        );
+        assert_eq!(
+            working_directory_sequence("", "/tmp/100%;#"),
+            "\x1b]7;file:///tmp/100%25%3B%23\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(
+            percent_encode("https://a.b/c?d=e%20"),
+            "https://a.b/c?d=e%20"
+        );
+        assert_eq!(percent_encode("/tmp/my file"), "/tmp/my%20file");
+        assert_eq!(percent_encode("a\x1b\\"), "a%1B\\");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+    }
+}
@@ -0,0 +1,227 @@
+//! Select Graphic Rendition (SGR) transitions
+//!
+//! Terminals keep the current style between writes, so switching from one
+//! style to the next only needs the fields that differ. This module finds
+//! the shortest `CSI ... m` sequence for a transition: either the delta
+//! (attribute "off" codes 22-29, default colors 39/49) or a full reset
+//! followed by the target style, whichever is fewer bytes.
+use crate::attr::{Attr, SGR_CODES};
+use crate::color::Color;
+
+/// The part of a cell's appearance controlled by SGR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Style {
+    pub attr: Attr,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Style {
+    /// Terminal defaults, what `CSI 0 m` resets to
+    pub(crate) const DEFAULT: Style = Style {
+        attr: Attr::NORMAL,
+        fg: Color::Reset,
+        bg: Color::Reset,
+    };
+
+    pub(crate) fn new(attr: Attr, fg: Color, bg: Color) -> Self {
+        Self { attr, fg, bg }
+    }
+}
+
+/// Append the shortest SGR sequence that turns style `from` into `to`
+///
+/// Nothing is written when the styles are equal.
+pub(crate) fn write_transition(out: &mut String, from: Style, to: Style) {
+    if from == to {
+        return;
+    }
+
+    // Build both candidates in place and drop the longer one
+    let start = out.len();
+    out.push_str("\x1b[");
+    write_delta(out, from, to);
+    out.push('m');
+    let middle = out.len();
+    out.push_str("\x1b[");
+    write_reset(out, to);
+    out.push('m');
+
+    if out.len() - middle < middle - start {
+        out.drain(start..middle);
+    } else {
+        out.truncate(middle);
+    }
+}
+
+/// Parameters changing only what differs between `from` and `to`
+fn write_delta(out: &mut String, from: Style, to: Style) {
+    let mut params = Params::new(out);
+
+    let removed = from.attr & !to.attr;
+    let mut added = to.attr & !from.attr;
+    let mut intensity_off = false;
+    for (attr, _, off) in SGR_CODES {
+        if removed.contains(attr) {
+            if attr == Attr::BOLD || attr == Attr::DIM {
+                // 22 clears both bold and dim, restore whichever stays on
+                if intensity_off {
+                    continue;
+                }
+                intensity_off = true;
+                added = added | (to.attr & (Attr::BOLD | Attr::DIM));
+            }
+            params.push(off);
+        }
+    }
+    for (attr, on, _) in SGR_CODES {
+        if added.contains(attr) {
+            params.push(on);
+        }
+    }
+
+    if from.fg != to.fg {
+        params.fg(to.fg);
+    }
+    if from.bg != to.bg {
+        params.bg(to.bg);
+    }
+}
+
+/// Append the SGR sequence setting style `to` whatever the current style:
+/// a reset followed by the fields of `to`
+pub(crate) fn write_style(out: &mut String, to: Style) {
+    out.push_str("\x1b[");
+    write_reset(out, to);
+    out.push('m');
+}
+
+/// Parameters resetting everything, then setting the whole of `to`
+fn write_reset(out: &mut String, to: Style) {
+    let mut params = Params::new(out);
+    params.push("0");
+    for code in to.attr.to_ansi_codes() {
+        params.push(code);
+    }
+    if to.fg != Color::Reset {
+        params.fg(to.fg);
+    }
+    if to.bg != Color::Reset {
+        params.bg(to.bg);
+    }
+}
+
+/// `;`-separated SGR parameter list
+struct Params<'a> {
+    out: &'a mut String,
+    empty: bool,
+}
+
+impl<'a> Params<'a> {
+    fn new(out: &'a mut String) -> Self {
+        Self { out, empty: true }
+    }
+
+    fn separate(&mut self) {
+        if !self.empty {
+            self.out.push(';');
+        }
+        self.empty = false;
+    }
+
+    fn push(&mut self, code: &str) {
+        self.separate();
+        self.out.push_str(code);
+    }
+
+    fn fg(&mut self, color: Color) {
+        self.separate();
+        color.write_ansi_fg(self.out);
+    }
+
+    fn bg(&mut self, color: Color) {
+        self.separate();
+        color.write_ansi_bg(self.out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(from: Style, to: Style) -> String {
+        let mut out = String::new();
+        write_transition(&mut out, from, to);
+        out
+    }
+
+    fn attr(attr: Attr) -> Style {
+        Style::new(attr, Color::Reset, Color::Reset)
+    }
+
+    #[test]
+    fn test_same_style_writes_nothing() {
+        let style = Style::new(Attr::BOLD, Color::Red, Color::Blue);
+        assert_eq!(transition(style, style), "");
+    }
+
+    #[test]
+    fn test_attribute_on() {
+        assert_eq!(transition(Style::DEFAULT, attr(Attr::BOLD)), "\x1b[1m");
+        assert_eq!(
+            transition(attr(Attr::BOLD), attr(Attr::BOLD | Attr::UNDERLINE)),
+            "\x1b[4m"
+        );
+    }
+
+    #[test]
+    fn test_attribute_off_uses_off_code() {
+        let from = Style::new(Attr::BOLD | Attr::ITALIC, Color::Red, Color::Blue);
+        let to = Style::new(Attr::BOLD, Color::Red, Color::Blue);
+        // 23 is shorter than resetting and re-sending bold and both colors
+        assert_eq!(transition(from, to), "\x1b[23m");
+    }
+
+    #[test]
+    fn test_reset_when_shorter() {
+        assert_eq!(transition(attr(Attr::BOLD), Style::DEFAULT), "\x1b[0m");
+        let from = Style::new(Attr::UNDERLINE, Color::Red, Color::Reset);
+        assert_eq!(transition(from, Style::DEFAULT), "\x1b[0m");
+    }
+
+    #[test]
+    fn test_bold_off_keeps_dim() {
+        let from = attr(Attr::BOLD | Attr::DIM | Attr::UNDERLINE);
+        let to = attr(Attr::DIM | Attr::UNDERLINE);
+        assert_eq!(transition(from, to), "\x1b[22;2m");
+    }
+
+    #[test]
+    fn test_color_changes() {
+        let from = Style::new(Attr::BOLD, Color::Red, Color::Blue);
+        let to = Style::new(Attr::BOLD, Color::Green, Color::Blue);
+        assert_eq!(transition(from, to), "\x1b[32m");
+
+        let to = Style::new(Attr::BOLD, Color::Reset, Color::Blue);
+        assert_eq!(transition(from, to), "\x1b[39m");
+
+        let to = Style::new(Attr::BOLD, Color::Red, Color::Rgb(1, 2, 3));
+        assert_eq!(transition(from, to), "\x1b[48;2;1;2;3m");
+    }
+
+    #[test]
+    fn test_shorter_candidate_wins() {
+        // "22" vs "0": reset is shorter
+        assert_eq!(transition(attr(Attr::DIM), Style::DEFAULT), "\x1b[0m");
+        // "24;1" vs "0;1": reset wins by a byte
+        assert_eq!(
+            transition(attr(Attr::UNDERLINE), attr(Attr::BOLD)),
+            "\x1b[0;1m"
+        );
+        // "4" vs "0;1;4": delta wins
+        assert_eq!(
+            transition(attr(Attr::BOLD), attr(Attr::BOLD | Attr::UNDERLINE)),
+            "\x1b[4m"
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! Vectorized line comparison and hashing
+//!
+//! Screen lines are slices of 8-byte [`PackedCell`](crate::style::PackedCell)s,
+//! which this module handles as `u64` words. Diffing compares several words
+//! per instruction, and the line hash keeps four independent lanes so it maps
+//! onto vector multiplies. x86_64 uses AVX2 when the CPU has it (checked at
+//! runtime) and SSE2 otherwise, aarch64 uses NEON, and other targets get the
+//! portable versions. All of them return the same results.
+
+/// Independent accumulators in the line hash
+const LANES: usize = 4;
+
+/// Per-lane keys mixed into the words of the first chunk
+const KEYS: [u64; LANES] = [
+    0xbe4ba423396cfeb8,
+    0x1cad21f72c81017c,
+    0xdb979083e96dd4de,
+    0x1f67b3b7a4a44072,
+];
+
+/// Added to every key for each following chunk, so that moving words
+/// between chunks changes the hash
+const KEY_STEP: u64 = 0x9e3779b97f4a7c15;
+
+const PRIME_1: u64 = 0x9e3779b185ebca87;
+const PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+
+/// First and last index where the two lines differ
+///
+/// Both slices must have the same length.
+pub fn find_diff(old: &[u64], new: &[u64]) -> Option<(usize, usize)> {
+    debug_assert_eq!(old.len(), new.len());
+    let first = first_diff(old, new)?;
+    let last = last_diff(&old[first..], &new[first..]).unwrap_or(0);
+    Some((first, first + last))
+}
+
+/// Hash of a line, for matching lines between frames
+pub fn hash_words(words: &[u64]) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was just checked
+            return unsafe { x86::hash_words_avx2(words) };
+        }
+        // SAFETY: SSE2 is part of the x86_64 baseline
+        unsafe { x86::hash_words_sse2(words) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the aarch64 baseline
+        unsafe { neon::hash_words(words) }
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        scalar::hash_words(words)
+    }
+}
+
+fn first_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was just checked
+            return unsafe { x86::first_diff_avx2(old, new) };
+        }
+        // SAFETY: SSE2 is part of the x86_64 baseline
+        unsafe { x86::first_diff_sse2(old, new) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the aarch64 baseline
+        unsafe { neon::first_diff(old, new) }
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        scalar::first_diff(old, new)
+    }
+}
+
+fn last_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was just checked
+            return unsafe { x86::last_diff_avx2(old, new) };
+        }
+        // SAFETY: SSE2 is part of the x86_64 baseline
+        unsafe { x86::last_diff_sse2(old, new) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the aarch64 baseline
+        unsafe { neon::last_diff(old, new) }
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        scalar::last_diff(old, new)
+    }
+}
+
+/// One accumulation step: the word itself plus the product of its halves
+/// after keying, which every vector unit can do per 64-bit lane
+#[inline(always)]
+fn accumulate(acc: u64, word: u64, key: u64) -> u64 {
+    let keyed = word ^ key;
+    acc.wrapping_add(word)
+        .wrapping_add((keyed & 0xffff_ffff).wrapping_mul(keyed >> 32))
+}
+
+/// Fold in the words left over after the last full chunk and mix the lanes
+/// into the final hash
+fn finish(mut acc: [u64; LANES], tail: &[u64], chunks: usize, len: usize) -> u64 {
+    let step = KEY_STEP.wrapping_mul(chunks as u64);
+    for (lane, &word) in tail.iter().enumerate() {
+        acc[lane] = accumulate(acc[lane], word, KEYS[lane].wrapping_add(step));
+    }
+
+    let mut hash = (len as u64).wrapping_mul(PRIME_1);
+    for lane in acc {
+        hash = (hash ^ (lane ^ lane >> 31)).wrapping_mul(PRIME_2);
+        hash = hash.rotate_left(27);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_1);
+    hash ^ hash >> 29
+}
+
+/// Portable versions, also the reference for the vector ones
+pub mod scalar {
+    use super::*;
+
+    pub fn first_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+        old.iter().zip(new).position(|(a, b)| a != b)
+    }
+
+    pub fn last_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+        old.iter().zip(new).rposition(|(a, b)| a != b)
+    }
+
+    pub fn find_diff(old: &[u64], new: &[u64]) -> Option<(usize, usize)> {
+        let first = first_diff(old, new)?;
+        Some((first, last_diff(old, new).unwrap_or(first)))
+    }
+
+    pub fn hash_words(words: &[u64]) -> u64 {
+        let mut acc = [0u64; LANES];
+        let mut chunks = words.chunks_exact(LANES);
+        let mut step = 0u64;
+        for chunk in &mut chunks {
+            for lane in 0..LANES {
+                acc[lane] = accumulate(acc[lane], chunk[lane], KEYS[lane].wrapping_add(step));
+            }
+            step = step.wrapping_add(KEY_STEP);
+        }
+        finish(acc, chunks.remainder(), words.len() / LANES, words.len())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::*;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub(super) fn first_diff_sse2(old: &[u64], new: &[u64]) -> Option<usize> {
+        let len = old.len().min(new.len());
+        let mut i = 0;
+        while i + 2 <= len {
+            // SAFETY: i + 2 <= len, unaligned loads
+            let (a, b) = unsafe {
+                (
+                    _mm_loadu_si128(old.as_ptr().add(i).cast()),
+                    _mm_loadu_si128(new.as_ptr().add(i).cast()),
+                )
+            };
+            // No 64-bit compare in SSE2: a word is equal when all 8 bytes are
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(a, b)) as u32;
+            if mask != 0xffff {
+                return Some(if mask & 0xff != 0xff { i } else { i + 1 });
+            }
+            i += 2;
+        }
+        scalar::first_diff(&old[i..len], &new[i..len]).map(|d| i + d)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) fn last_diff_sse2(old: &[u64], new: &[u64]) -> Option<usize> {
+        let mut end = old.len().min(new.len());
+        while end >= 2 {
+            let i = end - 2;
+            // SAFETY: i + 2 <= len, unaligned loads
+            let (a, b) = unsafe {
+                (
+                    _mm_loadu_si128(old.as_ptr().add(i).cast()),
+                    _mm_loadu_si128(new.as_ptr().add(i).cast()),
+                )
+            };
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(a, b)) as u32;
+            if mask != 0xffff {
+                return Some(if mask & 0xff00 != 0xff00 { i + 1 } else { i });
+            }
+            end = i;
+        }
+        scalar::last_diff(&old[..end], &new[..end])
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) fn hash_words_sse2(words: &[u64]) -> u64 {
+        let mut chunks = words.chunks_exact(LANES);
+        let mut acc = [_mm_setzero_si128(); 2];
+        let mut keys = [
+            _mm_set_epi64x(KEYS[1] as i64, KEYS[0] as i64),
+            _mm_set_epi64x(KEYS[3] as i64, KEYS[2] as i64),
+        ];
+        let step = _mm_set1_epi64x(KEY_STEP as i64);
+        for chunk in &mut chunks {
+            for half in 0..2 {
+                // SAFETY: chunk holds 4 words, unaligned load of 2 of them
+                let word = unsafe { _mm_loadu_si128(chunk.as_ptr().add(half * 2).cast()) };
+                let keyed = _mm_xor_si128(word, keys[half]);
+                let product = _mm_mul_epu32(keyed, _mm_srli_epi64::<32>(keyed));
+                acc[half] = _mm_add_epi64(acc[half], _mm_add_epi64(word, product));
+                keys[half] = _mm_add_epi64(keys[half], step);
+            }
+        }
+
+        let mut lanes = [0u64; LANES];
+        // SAFETY: lanes holds 4 words, unaligned stores of 2 each
+        unsafe {
+            _mm_storeu_si128(lanes.as_mut_ptr().cast(), acc[0]);
+            _mm_storeu_si128(lanes.as_mut_ptr().add(2).cast(), acc[1]);
+        }
+        finish(lanes, chunks.remainder(), words.len() / LANES, words.len())
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn first_diff_avx2(old: &[u64], new: &[u64]) -> Option<usize> {
+        let len = old.len().min(new.len());
+        let mut i = 0;
+        while i + 4 <= len {
+            // SAFETY: i + 4 <= len, unaligned loads
+            let (a, b) = unsafe {
+                (
+                    _mm256_loadu_si256(old.as_ptr().add(i).cast()),
+                    _mm256_loadu_si256(new.as_ptr().add(i).cast()),
+                )
+            };
+            let equal = _mm256_cmpeq_epi64(a, b);
+            let mask = _mm256_movemask_pd(_mm256_castsi256_pd(equal)) as u32;
+            if mask != 0xf {
+                return Some(i + (!mask & 0xf).trailing_zeros() as usize);
+            }
+            i += 4;
+        }
+        first_diff_sse2(&old[i..len], &new[i..len]).map(|d| i + d)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn last_diff_avx2(old: &[u64], new: &[u64]) -> Option<usize> {
+        let mut end = old.len().min(new.len());
+        while end >= 4 {
+            let i = end - 4;
+            // SAFETY: i + 4 <= len, unaligned loads
+            let (a, b) = unsafe {
+                (
+                    _mm256_loadu_si256(old.as_ptr().add(i).cast()),
+                    _mm256_loadu_si256(new.as_ptr().add(i).cast()),
+                )
+            };
+            let equal = _mm256_cmpeq_epi64(a, b);
+            let mask = _mm256_movemask_pd(_mm256_castsi256_pd(equal)) as u32;
+            if mask != 0xf {
+                return Some(i + 31 - (!mask & 0xf).leading_zeros() as usize);
+            }
+            end = i;
+        }
+        last_diff_sse2(&old[..end], &new[..end])
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn hash_words_avx2(words: &[u64]) -> u64 {
+        let mut chunks = words.chunks_exact(LANES);
+        let mut acc = _mm256_setzero_si256();
+        let mut keys = _mm256_set_epi64x(
+            KEYS[3] as i64,
+            KEYS[2] as i64,
+            KEYS[1] as i64,
+            KEYS[0] as i64,
+        );
+        let step = _mm256_set1_epi64x(KEY_STEP as i64);
+        for chunk in &mut chunks {
+            // SAFETY: chunk holds 4 words, unaligned load
+            let word = unsafe { _mm256_loadu_si256(chunk.as_ptr().cast()) };
+            let keyed = _mm256_xor_si256(word, keys);
+            let product = _mm256_mul_epu32(keyed, _mm256_srli_epi64::<32>(keyed));
+            acc = _mm256_add_epi64(acc, _mm256_add_epi64(word, product));
+            keys = _mm256_add_epi64(keys, step);
+        }
+
+        let mut lanes = [0u64; LANES];
+        // SAFETY: lanes holds 4 words, unaligned store
+        unsafe { _mm256_storeu_si256(lanes.as_mut_ptr().cast(), acc) };
+        finish(lanes, chunks.remainder(), words.len() / LANES, words.len())
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::*;
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) fn first_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+        let len = old.len().min(new.len());
+        let mut i = 0;
+        while i + 2 <= len {
+            // SAFETY: i + 2 <= len
+            let equal = unsafe {
+                vceqq_u64(
+                    vld1q_u64(old.as_ptr().add(i)),
+                    vld1q_u64(new.as_ptr().add(i)),
+                )
+            };
+            if vminvq_u32(vreinterpretq_u32_u64(equal)) != u32::MAX {
+                return Some(if vgetq_lane_u64::<0>(equal) == 0 {
+                    i
+                } else {
+                    i + 1
+                });
+            }
+            i += 2;
+        }
+        scalar::first_diff(&old[i..len], &new[i..len]).map(|d| i + d)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) fn last_diff(old: &[u64], new: &[u64]) -> Option<usize> {
+        let mut end = old.len().min(new.len());
+        while end >= 2 {
+            let i = end - 2;
+            // SAFETY: i + 2 <= len
+            let equal = unsafe {
+                vceqq_u64(
+                    vld1q_u64(old.as_ptr().add(i)),
+                    vld1q_u64(new.as_ptr().add(i)),
+                )
+            };
+            if vminvq_u32(vreinterpretq_u32_u64(equal)) != u32::MAX {
+                return Some(if vgetq_lane_u64::<1>(equal) == 0 {
+                    i + 1
+                } else {
+                    i
+                });
+            }
+            end = i;
+        }
+        scalar::last_diff(&old[..end], &new[..end])
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) fn hash_words(words: &[u64]) -> u64 {
+        let mut chunks = words.chunks_exact(LANES);
+        let mut acc = [vdupq_n_u64(0); 2];
+        // SAFETY: KEYS holds 4 words
+        let mut keys = unsafe { [vld1q_u64(KEYS.as_ptr()), vld1q_u64(KEYS.as_ptr().add(2))] };
+        let step = vdupq_n_u64(KEY_STEP);
+        for chunk in &mut chunks {
+            for half in 0..2 {
+                // SAFETY: chunk holds 4 words
+                let word = unsafe { vld1q_u64(chunk.as_ptr().add(half * 2)) };
+                let keyed = veorq_u64(word, keys[half]);
+                let product = vmull_u32(vmovn_u64(keyed), vshrn_n_u64::<32>(keyed));
+                acc[half] = vaddq_u64(acc[half], vaddq_u64(word, product));
+                keys[half] = vaddq_u64(keys[half], step);
+            }
+        }
+
+        let mut lanes = [0u64; LANES];
+        // SAFETY: lanes holds 4 words
+        unsafe {
+            vst1q_u64(lanes.as_mut_ptr(), acc[0]);
+            vst1q_u64(lanes.as_mut_ptr().add(2), acc[1]);
+        }
+        finish(lanes, chunks.remainder(), words.len() / LANES, words.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of every length up to 40 words with one difference at each spot
+    fn cases() -> impl Iterator<Item = (Vec<u64>, Vec<u64>)> {
+        (0..40).flat_map(|len| {
+            let old: Vec<u64> = (0..len as u64).map(|i| i * 0x1_0000_0001).collect();
+            (0..=len).map(move |at| {
+                let mut new = old.clone();
+                if at < len {
+                    new[at] ^= 1 << (at % 64);
+                }
+                (old.clone(), new)
+            })
+        })
+    }
+
+    #[test]
+    fn test_find_diff_matches_scalar() {
+        for (old, new) in cases() {
+            assert_eq!(find_diff(&old, &new), scalar::find_diff(&old, &new));
+        }
+    }
+
+    #[test]
+    fn test_find_diff_first_and_last() {
+        let old = vec![0u64; 37];
+        let mut new = old.clone();
+        new[3] = 1;
+        new[30] = 2;
+        assert_eq!(find_diff(&old, &new), Some((3, 30)));
+        assert_eq!(find_diff(&old, &old), None);
+        assert_eq!(find_diff(&[], &[]), None);
+
+        // Difference only in the upper half of a word
+        new = old.clone();
+        new[8] = 1 << 63;
+        assert_eq!(find_diff(&old, &new), Some((8, 8)));
+    }
+
+    #[test]
+    fn test_hash_matches_scalar() {
+        for len in 0..40 {
+            let words: Vec<u64> = (0..len as u64).map(|i| i.wrapping_mul(PRIME_1)).collect();
+            assert_eq!(hash_words(&words), scalar::hash_words(&words));
+        }
+    }
+
+    #[test]
+    fn test_hash_depends_on_order_and_length() {
+        let words: Vec<u64> = (0..16).collect();
+        let mut swapped = words.clone();
+        swapped.swap(0, 4); // same lane, different chunk
+        let mut rotated = words.clone();
+        rotated.swap(0, 1); // different lane
+
+        assert_ne!(hash_words(&words), hash_words(&swapped));
+        assert_ne!(hash_words(&words), hash_words(&rotated));
+        assert_ne!(hash_words(&words), hash_words(&words[..15]));
+        assert_ne!(hash_words(&[0]), hash_words(&[0, 0]));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_sse2_matches_scalar() {
+        // The dispatching functions pick AVX2 when available, check SSE2 too
+        for (old, new) in cases() {
+            let (first, last) = unsafe {
+                (
+                    x86::first_diff_sse2(&old, &new),
+                    x86::last_diff_sse2(&old, &new),
+                )
+            };
+            assert_eq!(first, scalar::first_diff(&old, &new));
+            assert_eq!(last, scalar::last_diff(&old, &new));
+            assert_eq!(
+                unsafe { x86::hash_words_sse2(&new) },
+                scalar::hash_words(&new)
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Render statistics
+//!
+//! Every [`Screen::refresh`](crate::Screen::refresh) records what it did in a
+//! [`FrameStats`], and the totals since the screen was created are kept in
+//! [`RenderStats`]. Useful to find out why frames are slow on a given link.
+use std::time::Duration;
+
+/// What a single refresh did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Bytes written to the terminal
+    pub bytes_written: usize,
+    /// Cells sent to the terminal (printed, repeated or erased)
+    pub cells_changed: usize,
+    /// Dirty lines compared against the current screen contents
+    pub lines_diffed: usize,
+    /// Scroll operations (insert/delete line) found by scroll detection
+    pub scroll_ops: usize,
+    /// SGR sequences emitted to switch styles
+    pub style_switches: usize,
+    /// The refresh stopped early because input was pending
+    pub aborted: bool,
+    /// Time spent hashing dirty lines and detecting scrolls
+    pub hash_time: Duration,
+    /// Time spent diffing lines and encoding the output
+    pub diff_time: Duration,
+    /// Time spent writing the output to the terminal
+    pub write_time: Duration,
+}
+
+impl FrameStats {
+    /// Total time spent in the refresh
+    pub fn total_time(&self) -> Duration {
+        self.hash_time + self.diff_time + self.write_time
+    }
+}
+
+/// Totals over every refresh since the screen was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Number of refreshes
+    pub frames: u64,
+    /// Refreshes aborted by pending input
+    pub aborted_frames: u64,
+    /// Refreshes put off by the [`RefreshPolicy`](crate::RefreshPolicy)
+    pub deferred_refreshes: u64,
+    /// Sums of the [`FrameStats`] fields of the same name
+    pub bytes_written: u64,
+    pub cells_changed: u64,
+    pub lines_diffed: u64,
+    pub scroll_ops: u64,
+    pub style_switches: u64,
+    pub hash_time: Duration,
+    pub diff_time: Duration,
+    pub write_time: Duration,
+}
+
+impl RenderStats {
+    /// Add one frame to the totals
+    pub(crate) fn record(&mut self, frame: &FrameStats) {
+        self.frames += 1;
+        self.aborted_frames += frame.aborted as u64;
+        self.bytes_written += frame.bytes_written as u64;
+        self.cells_changed += frame.cells_changed as u64;
+        self.lines_diffed += frame.lines_diffed as u64;
+        self.scroll_ops += frame.scroll_ops as u64;
+        self.style_switches += frame.style_switches as u64;
+        self.hash_time += frame.hash_time;
+        self.diff_time += frame.diff_time;
+        self.write_time += frame.write_time;
+    }
+
+    /// Average bytes written per frame
+    pub fn bytes_per_frame(&self) -> f64 {
+        if self.frames == 0 {
+            0.0
+        } else {
+            self.bytes_written as f64 / self.frames as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates() {
+        let mut totals = RenderStats::default();
+        let frame = FrameStats {
+            bytes_written: 100,
+            cells_changed: 10,
+            lines_diffed: 2,
+            scroll_ops: 1,
+            style_switches: 3,
+            aborted: true,
+            hash_time: Duration::from_micros(5),
+            diff_time: Duration::from_micros(7),
+            write_time: Duration::from_micros(11),
+        };
+
+        totals.record(&frame);
+        totals.record(&FrameStats::default());
+
+        assert_eq!(totals.frames, 2);
+        assert_eq!(totals.aborted_frames, 1);
+        assert_eq!(totals.bytes_written, 100);
+        assert_eq!(totals.style_switches, 3);
+        assert_eq!(totals.write_time, Duration::from_micros(11));
+        assert_eq!(totals.bytes_per_frame(), 50.0);
+        assert_eq!(frame.total_time(), Duration::from_micros(23));
+    }
+
+    #[test]
+    fn test_bytes_per_frame_without_frames() {
+        assert_eq!(RenderStats::default().bytes_per_frame(), 0.0);
+    }
+}
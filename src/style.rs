@@ -0,0 +1,299 @@
+//! Interned cell styles
+//!
+//! The screen buffers hold [`PackedCell`]s: a character and a [`StyleId`]
+//! into the screen's [`StyleTable`], 8 bytes instead of a full `Cell`.
+//! Each distinct combination of attributes, colors, hyperlink and color pair
+//! is stored once, so two cells look the same exactly when their ids are
+//! equal, and diffing and hashing a line only compares integers.
+use crate::attr::Attr;
+#[cfg(test)]
+use crate::cell::Cell;
+use crate::color::Color;
+use crate::sgr::{self, Style};
+use std::collections::HashMap;
+
+/// Index of a style in a [`StyleTable`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct StyleId(u32);
+
+impl StyleId {
+    /// Terminal defaults: no attributes, default colors, no hyperlink or pair
+    pub const DEFAULT: StyleId = StyleId(0);
+
+    /// Id of the `index`th style interned in a table
+    pub const fn new(index: u32) -> Self {
+        StyleId(index)
+    }
+}
+
+/// A character and its interned style
+///
+/// Laid out as a single aligned `u64` so lines can be compared and hashed
+/// as words (see `simd`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C, align(8))]
+pub struct PackedCell {
+    pub ch: char,
+    pub style: StyleId,
+}
+
+impl PackedCell {
+    /// Space in the default style
+    pub const BLANK: PackedCell = PackedCell {
+        ch: ' ',
+        style: StyleId::DEFAULT,
+    };
+
+    pub fn new(ch: char, style: StyleId) -> Self {
+        Self { ch, style }
+    }
+
+    /// View a line of cells as words
+    #[inline]
+    pub fn as_words(cells: &[PackedCell]) -> &[u64] {
+        // SAFETY: PackedCell is 8 bytes with 8-byte alignment and no padding
+        // (a char and a u32), so every cell is a fully initialized u64
+        unsafe { std::slice::from_raw_parts(cells.as_ptr().cast(), cells.len()) }
+    }
+}
+
+/// Where a Kitty image placeholder cell sits in its image
+///
+/// The column is the cell's character, the diacritic numbering it, so a
+/// row of an image needs one style rather than one per cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct PlaceholderRow {
+    pub row: u16,
+    /// Bits 24-31 of the image id (the color holds the rest)
+    pub id_msb: u8,
+}
+
+/// Everything about a cell's appearance except the character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct CellStyle {
+    pub attr: Attr,
+    pub fg: Color,
+    pub bg: Color,
+    /// Hyperlink id into the Screen's URI table (0 = no link)
+    pub hyperlink: u16,
+    /// Color pair the fg/bg came from (0 = none)
+    pub pair: u16,
+    /// Image row of a Kitty placeholder cell (None = ordinary cell)
+    pub placeholder: Option<PlaceholderRow>,
+}
+
+impl CellStyle {
+    pub(crate) const DEFAULT: CellStyle = CellStyle {
+        attr: Attr::NORMAL,
+        fg: Color::Reset,
+        bg: Color::Reset,
+        hyperlink: 0,
+        pair: 0,
+        placeholder: None,
+    };
+
+    /// The part of the style set with SGR
+    pub(crate) fn sgr(&self) -> Style {
+        Style::new(self.attr, self.fg, self.bg)
+    }
+}
+
+/// Number of SGR transitions cached before the cache starts over
+const MAX_CACHED_TRANSITIONS: usize = 4096;
+
+/// Styles used on a screen, each stored once
+pub(crate) struct StyleTable {
+    styles: Vec<CellStyle>,
+    ids: HashMap<CellStyle, StyleId>,
+    // SGR sequences already worked out: (from, to) -> range in sgr_text
+    transitions: HashMap<(StyleId, StyleId), (u32, u32)>,
+    sgr_text: String,
+}
+
+impl StyleTable {
+    pub(crate) fn new() -> Self {
+        let mut table = Self {
+            styles: Vec::new(),
+            ids: HashMap::new(),
+            transitions: HashMap::new(),
+            sgr_text: String::new(),
+        };
+        table.intern(CellStyle::DEFAULT);
+        table
+    }
+
+    /// Number of distinct styles
+    pub(crate) fn len(&self) -> usize {
+        self.styles.len()
+    }
+
+    /// Id of `style`, adding it to the table if it's new
+    pub(crate) fn intern(&mut self, style: CellStyle) -> StyleId {
+        if let Some(&id) = self.ids.get(&style) {
+            return id;
+        }
+        let id = StyleId(self.styles.len() as u32);
+        self.styles.push(style);
+        self.ids.insert(style, id);
+        id
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, id: StyleId) -> &CellStyle {
+        &self.styles[id.0 as usize]
+    }
+
+    /// Expand a packed cell back into a full [`Cell`]
+    #[cfg(test)]
+    pub(crate) fn unpack(&self, cell: PackedCell) -> Cell {
+        let style = self.get(cell.style);
+        let mut unpacked = Cell::with_style(cell.ch, style.attr, style.fg, style.bg);
+        unpacked.hyperlink = style.hyperlink;
+        unpacked.pair = style.pair;
+        unpacked
+    }
+
+    /// Give every style using color pair `pair` the colors `fg`/`bg`
+    ///
+    /// Returns the id each affected style now maps to.
+    pub(crate) fn recolor_pair(
+        &mut self,
+        pair: u16,
+        fg: Color,
+        bg: Color,
+    ) -> HashMap<StyleId, StyleId> {
+        let mut recolored = HashMap::new();
+        for i in 0..self.styles.len() {
+            let style = self.styles[i];
+            if style.pair == pair {
+                let new = self.intern(CellStyle { fg, bg, ..style });
+                recolored.insert(StyleId(i as u32), new);
+            }
+        }
+        recolored
+    }
+
+    /// Append the SGR sequence switching from style `from` to `to`
+    ///
+    /// Same output as [`sgr::write_transition`], but each pair of styles is
+    /// only worked out once.
+    pub(crate) fn write_transition(&mut self, out: &mut String, from: StyleId, to: StyleId) {
+        if from == to {
+            return;
+        }
+        if let Some(&(start, end)) = self.transitions.get(&(from, to)) {
+            out.push_str(&self.sgr_text[start as usize..end as usize]);
+            return;
+        }
+
+        if self.transitions.len() >= MAX_CACHED_TRANSITIONS {
+            self.transitions.clear();
+            self.sgr_text.clear();
+        }
+        let (from_sgr, to_sgr) = (self.get(from).sgr(), self.get(to).sgr());
+        let start = self.sgr_text.len();
+        sgr::write_transition(&mut self.sgr_text, from_sgr, to_sgr);
+        let end = self.sgr_text.len();
+        self.transitions
+            .insert((from, to), (start as u32, end as u32));
+        out.push_str(&self.sgr_text[start..end]);
+    }
+
+    /// Drop the styles no longer in use
+    ///
+    /// `live` yields every id still referenced, and each is rewritten to its
+    /// id in the compacted table.
+    pub(crate) fn compact<'a>(&mut self, live: impl Iterator<Item = &'a mut StyleId>) {
+        let old = std::mem::replace(self, Self::new());
+        let mut remap: Vec<Option<StyleId>> = vec![None; old.len()];
+        for id in live {
+            let new = *remap[id.0 as usize].get_or_insert_with(|| self.intern(*old.get(*id)));
+            *id = new;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(attr: Attr, fg: Color) -> CellStyle {
+        CellStyle {
+            attr,
+            fg,
+            ..CellStyle::DEFAULT
+        }
+    }
+
+    #[test]
+    fn test_packed_cell_size() {
+        assert_eq!(std::mem::size_of::<PackedCell>(), 8);
+    }
+
+    #[test]
+    fn test_intern_reuses_ids() {
+        let mut table = StyleTable::new();
+        assert_eq!(table.intern(CellStyle::DEFAULT), StyleId::DEFAULT);
+
+        let bold = table.intern(style(Attr::BOLD, Color::Red));
+        let italic = table.intern(style(Attr::ITALIC, Color::Red));
+        assert_ne!(bold, italic);
+        assert_eq!(table.intern(style(Attr::BOLD, Color::Red)), bold);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(bold).attr, Attr::BOLD);
+    }
+
+    #[test]
+    fn test_unpack() {
+        let mut table = StyleTable::new();
+        let id = table.intern(CellStyle {
+            hyperlink: 2,
+            ..style(Attr::UNDERLINE, Color::Blue)
+        });
+
+        let cell = table.unpack(PackedCell::new('x', id));
+        let mut expected = Cell::with_style('x', Attr::UNDERLINE, Color::Blue, Color::Reset);
+        expected.set_hyperlink(2);
+        assert_eq!(cell, expected);
+        assert_eq!(table.unpack(PackedCell::BLANK), Cell::blank());
+    }
+
+    #[test]
+    fn test_cached_transition_matches_sgr() {
+        let mut table = StyleTable::new();
+        let bold = table.intern(style(Attr::BOLD, Color::Red));
+
+        let mut expected = String::new();
+        sgr::write_transition(
+            &mut expected,
+            Style::DEFAULT,
+            style(Attr::BOLD, Color::Red).sgr(),
+        );
+
+        for _ in 0..2 {
+            let mut out = String::new();
+            table.write_transition(&mut out, StyleId::DEFAULT, bold);
+            assert_eq!(out, expected);
+        }
+
+        let mut out = String::new();
+        table.write_transition(&mut out, bold, bold);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn test_compact_remaps_live_ids() {
+        let mut table = StyleTable::new();
+        let _unused = table.intern(style(Attr::BOLD, Color::Red));
+        let used = table.intern(style(Attr::DIM, Color::Green));
+
+        let mut ids = [used, StyleId::DEFAULT, used];
+        table.compact(ids.iter_mut());
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(ids[0], ids[2]);
+        assert_eq!(ids[1], StyleId::DEFAULT);
+        assert_eq!(table.get(ids[0]).attr, Attr::DIM);
+    }
+}
@@ -0,0 +1,90 @@
+//! Refresh rate limiting
+//!
+//! Applications often call [`Screen::refresh`](crate::Screen::refresh) far
+//! more often than the terminal can usefully show, e.g. once per log line.
+//! With a [`RefreshPolicy`] set, refreshes that come too soon after the last
+//! frame are deferred: changes keep accumulating in the dirty regions and go
+//! out together with the next frame.
+use std::time::{Duration, Instant};
+
+/// How often the screen may be redrawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RefreshPolicy {
+    min_interval: Duration,
+}
+
+impl RefreshPolicy {
+    /// Draw every refresh immediately (the default)
+    pub const fn unlimited() -> Self {
+        Self {
+            min_interval: Duration::ZERO,
+        }
+    }
+
+    /// Draw at most `fps` frames per second (0 = unlimited)
+    pub fn max_fps(fps: u32) -> Self {
+        if fps == 0 {
+            return Self::unlimited();
+        }
+        Self {
+            min_interval: Duration::from_secs(1) / fps,
+        }
+    }
+
+    /// Leave at least `interval` between the start of two frames
+    pub const fn min_interval(interval: Duration) -> Self {
+        Self {
+            min_interval: interval,
+        }
+    }
+
+    /// Minimum time between two frames
+    pub fn interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    /// How long until a frame may be drawn, given when the last one was
+    ///
+    /// Zero when a frame may be drawn right away.
+    pub(crate) fn wait_time(&self, last_frame: Option<Instant>, now: Instant) -> Duration {
+        match last_frame {
+            Some(last) => (last + self.min_interval).saturating_duration_since(now),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_fps() {
+        assert_eq!(
+            RefreshPolicy::max_fps(50).interval(),
+            Duration::from_millis(20)
+        );
+        assert_eq!(RefreshPolicy::max_fps(0), RefreshPolicy::unlimited());
+        assert_eq!(RefreshPolicy::default(), RefreshPolicy::unlimited());
+    }
+
+    #[test]
+    fn test_wait_time() {
+        let policy = RefreshPolicy::min_interval(Duration::from_millis(100));
+        let start = Instant::now();
+
+        assert_eq!(policy.wait_time(None, start), Duration::ZERO);
+        assert_eq!(
+            policy.wait_time(Some(start), start + Duration::from_millis(30)),
+            Duration::from_millis(70)
+        );
+        assert_eq!(
+            policy.wait_time(Some(start), start + Duration::from_millis(150)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            RefreshPolicy::unlimited().wait_time(Some(start), start),
+            Duration::ZERO
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use crate::attr::Attr;
 use crate::color::Color;
 use crate::error::{Error, Result};
-use smallvec::SmallVec;
+use crate::image::MAX_PLACEHOLDER_CELLS;
+use crate::sgr::Style;
 use std::fmt::Write;
 use std::io;
 
@@ -19,11 +20,7 @@ pub struct Window {
     buffer: String,
     scroll_enabled: bool,
     // Performance optimization: track last emitted style to avoid redundant codes
-    last_emitted_attr: Attr,
-    last_emitted_fg: Color,
-    last_emitted_bg: Color,
-    // Performance optimization: SmallVec for style sequence (stack-allocated for <64 bytes)
-    style_sequence_buf: SmallVec<[u8; 64]>,
+    last_emitted_style: Style,
 }
 
 impl Window {
@@ -44,10 +41,7 @@ impl Window {
             current_bg: Color::Reset,
             buffer: String::with_capacity(estimated_capacity),
             scroll_enabled: false,
-            last_emitted_attr: Attr::NORMAL,
-            last_emitted_fg: Color::Reset,
-            last_emitted_bg: Color::Reset,
-            style_sequence_buf: SmallVec::new(), // Stack-allocated for sequences <64 bytes
+            last_emitted_style: Style::DEFAULT,
         })
     }
 
@@ -67,32 +61,10 @@ impl Window {
             return Err(Error::InvalidCoordinates { y, x });
         }
 
-        // Performance optimization: use relative cursor movement for short distances
-        let dy = (y as i32 - self.cursor_y as i32).abs();
-        let dx = (x as i32 - self.cursor_x as i32).abs();
-
-        let abs_y = self.begin_y + y;
-        let abs_x = self.begin_x + x;
-
-        // Threshold: use relative movement if distance < 4 cells
-        if dy == 0 && dx > 0 && dx < 4 {
-            // Horizontal movement only
-            if x > self.cursor_x {
-                write!(self.buffer, "\x1b[{}C", dx)?; // CUF - Cursor Forward
-            } else {
-                write!(self.buffer, "\x1b[{}D", dx)?; // CUB - Cursor Back
-            }
-        } else if dx == 0 && dy > 0 && dy < 4 {
-            // Vertical movement only
-            if y > self.cursor_y {
-                write!(self.buffer, "\x1b[{}B", dy)?; // CUD - Cursor Down
-            } else {
-                write!(self.buffer, "\x1b[{}A", dy)?; // CUU - Cursor Up
-            }
-        } else {
-            // Use absolute positioning for long distances or diagonal movement
-            write!(self.buffer, "\x1b[{};{}H", abs_y + 1, abs_x + 1)?; // CUP - Cursor Position
-        }
+        // Cheapest move on the screen, the window's origin added
+        let from = (self.begin_y + self.cursor_y, self.begin_x + self.cursor_x);
+        let to = (self.begin_y + y, self.begin_x + x);
+        crate::mvcur::mvcur(&mut self.buffer, Some(from), to, None);
 
         self.cursor_y = y;
         self.cursor_x = x;
@@ -147,6 +119,41 @@ impl Window {
         self.addch(ch)
     }
 
+    /// Draw a Kitty image as placeholder cells, `height` x `width` cells
+    /// from (y, x)
+    ///
+    /// The image needs a virtual placement, see
+    /// [`Screen::create_kitty_placeholder`](crate::Screen::create_kitty_placeholder).
+    /// The part outside the window is clipped.
+    pub fn put_kitty_placeholder(
+        &mut self,
+        image_id: u32,
+        y: u16,
+        x: u16,
+        height: u16,
+        width: u16,
+    ) -> Result<()> {
+        if x >= self.width {
+            return Ok(());
+        }
+
+        let columns = 0..width.min(self.width - x).min(MAX_PLACEHOLDER_CELLS);
+        let fg = self.current_fg;
+        self.current_fg = crate::image::placeholder_color(image_id);
+        for row in 0..height.min(MAX_PLACEHOLDER_CELLS) {
+            let Some(win_y) = y.checked_add(row).filter(|&win_y| win_y < self.height) else {
+                break;
+            };
+            self.move_cursor(win_y, x)?;
+            self.apply_style()?;
+            let text = crate::image::placeholder_text(image_id, row, columns.clone());
+            self.buffer.push_str(&text);
+            self.cursor_x += columns.len() as u16;
+        }
+        self.current_fg = fg;
+        Ok(())
+    }
+
     /// Turn on attributes
     pub fn attron(&mut self, attr: Attr) -> Result<()> {
         self.current_attr = self.current_attr | attr;
@@ -242,6 +249,7 @@ impl Window {
     /// Refresh the window (flush buffer to stdout)
     pub fn refresh(&mut self) -> Result<()> {
         use std::io::Write as IoWrite;
+        self.reset_style();
         io::stdout().write_all(self.buffer.as_bytes())?;
         io::stdout().flush()?;
         self.buffer.clear();
@@ -251,6 +259,7 @@ impl Window {
     /// Update internal buffer without refreshing screen
     pub fn wnoutrefresh(&mut self) -> Result<()> {
         use crate::backend::Backend;
+        self.reset_style();
         Backend::add_to_update_buffer(&self.buffer)?;
         self.buffer.clear();
         Ok(())
@@ -298,76 +307,23 @@ impl Window {
     }
 
     fn apply_style(&mut self) -> Result<()> {
-        // Performance optimization: only emit ANSI codes if style changed since last emission
-        let style_changed = self.current_attr != self.last_emitted_attr
-            || self.current_fg != self.last_emitted_fg
-            || self.current_bg != self.last_emitted_bg;
-
-        if !style_changed {
-            return Ok(());
-        }
-
-        // Performance optimization: use SmallVec (stack-allocated)
-        self.style_sequence_buf.clear();
-        let mut needs_separator = false;
-
-        // If any attribute changed, we need to reset and re-apply all
-        // (ANSI doesn't support selective attribute removal)
-        if self.current_attr != self.last_emitted_attr {
-            // Reset all attributes first
-            if self.last_emitted_attr != Attr::NORMAL {
-                self.style_sequence_buf.push(b'0');
-                needs_separator = true;
-            }
-
-            // Add current attribute codes
-            if !self.current_attr.is_empty() {
-                for code in self.current_attr.to_ansi_codes() {
-                    if needs_separator {
-                        self.style_sequence_buf.push(b';');
-                    }
-                    self.style_sequence_buf.extend_from_slice(code.as_bytes());
-                    needs_separator = true;
-                }
-            }
-        }
-
-        // Add color codes if changed (using temporary buffer for String conversion)
-        let mut color_buf = String::with_capacity(20);
-        if self.current_fg != self.last_emitted_fg {
-            if needs_separator {
-                self.style_sequence_buf.push(b';');
-            }
-            color_buf.clear();
-            self.current_fg.write_ansi_fg(&mut color_buf);
-            self.style_sequence_buf
-                .extend_from_slice(color_buf.as_bytes());
-            needs_separator = true;
-        }
-        if self.current_bg != self.last_emitted_bg {
-            if needs_separator {
-                self.style_sequence_buf.push(b';');
-            }
-            color_buf.clear();
-            self.current_bg.write_ansi_bg(&mut color_buf);
-            self.style_sequence_buf
-                .extend_from_slice(color_buf.as_bytes());
-        }
-
-        if !self.style_sequence_buf.is_empty() {
-            self.buffer.push_str("\x1b[");
-            self.buffer
-                .push_str(std::str::from_utf8(&self.style_sequence_buf).unwrap());
-            self.buffer.push('m');
-        }
+        // Performance optimization: only emit the style fields that changed since last emission
+        let current = Style::new(self.current_attr, self.current_fg, self.current_bg);
+        crate::sgr::write_transition(&mut self.buffer, self.last_emitted_style, current);
 
         // Update last emitted state
-        self.last_emitted_attr = self.current_attr;
-        self.last_emitted_fg = self.current_fg;
-        self.last_emitted_bg = self.current_bg;
+        self.last_emitted_style = current;
 
         Ok(())
     }
+
+    /// Leave the terminal in the default style for whatever is drawn next
+    fn reset_style(&mut self) {
+        if self.last_emitted_style != Style::DEFAULT {
+            self.buffer.push_str("\x1b[0m");
+            self.last_emitted_style = Style::DEFAULT;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -617,6 +573,23 @@ mod tests {
         assert!(win.buffer.contains("\x1b[0m"));
     }
 
+    #[test]
+    fn test_window_output_ends_in_default_style() {
+        let mut win = Window::new(10, 20, 0, 0).unwrap();
+
+        win.attron(Attr::BOLD).unwrap();
+        win.print("Bold").unwrap();
+        win.reset_style();
+        assert!(win.buffer.ends_with("Bold\x1b[0m"));
+
+        // Nothing to reset the next time
+        win.buffer.clear();
+        win.attroff(Attr::BOLD).unwrap();
+        win.print("Normal").unwrap();
+        win.reset_style();
+        assert_eq!(win.buffer, "Normal");
+    }
+
     #[test]
     fn test_window_style_caching_multiple_attrs() {
         let mut win = Window::new(10, 20, 0, 0).unwrap();
@@ -685,9 +658,9 @@ mod tests {
         win.cursor_x = 5;
         win.cursor_y = 3;
 
-        // Move down 2 lines (should use CUD)
+        // Move down 2 lines (LF keeps the column in raw mode)
         win.move_cursor(5, 5).unwrap();
-        assert!(win.buffer.contains("\x1b[2B")); // Cursor Down 2
+        assert_eq!(win.buffer, "\n\n");
         assert_eq!(win.cursor_x, 5);
         assert_eq!(win.cursor_y, 5);
     }
@@ -698,13 +671,16 @@ mod tests {
         win.cursor_x = 2;
         win.cursor_y = 1;
 
-        // Move 10 cells forward (should use CUP)
+        // Move 10 cells forward (CUF is shorter than CUP)
         win.move_cursor(1, 12).unwrap();
-        // abs_y = 5 + 1 = 6, abs_x = 5 + 12 = 17
-        // In 1-based: row 7, col 18
-        assert!(win.buffer.contains("\x1b[7;18H")); // CUP
-        assert_eq!(win.cursor_x, 12);
-        assert_eq!(win.cursor_y, 1);
+        assert_eq!(win.buffer, "\x1b[10C");
+
+        // Far enough for CUP: abs_y = 5 + 8 = 13, abs_x = 5 + 0 = 5, so
+        // row 14, col 6 in 1-based
+        win.move_cursor(8, 0).unwrap();
+        assert!(win.buffer.ends_with("\x1b[14;6H"));
+        assert_eq!(win.cursor_x, 0);
+        assert_eq!(win.cursor_y, 8);
     }
 
     #[test]
@@ -773,4 +749,21 @@ mod tests {
         assert_eq!(win.buffer, "Hello");
         assert_eq!(win.cursor_x, 5);
     }
+
+    #[test]
+    fn test_window_kitty_placeholder_clipped() {
+        let mut win = Window::new(3, 4, 2, 10).unwrap();
+        win.put_kitty_placeholder(7, 1, 2, 5, 5).unwrap();
+
+        // Two rows and two columns fit in the window
+        assert_eq!(win.buffer.matches('\u{10EEEE}').count(), 4);
+        assert!(win.buffer.contains("\x1b[38;2;0;0;7m"));
+        assert!(
+            win.buffer
+                .starts_with("\n\x1b[2C\x1b[38;2;0;0;7m\u{10EEEE}\u{0305}\u{0305}")
+        );
+        assert!(win.buffer.contains("\u{10EEEE}\u{030D}\u{030D}"));
+        // The window's own color is kept
+        assert_eq!(win.current_fg, Color::Reset);
+    }
 }
@@ -0,0 +1,87 @@
+//! Allocation-free output for the refresh loop
+//!
+//! Frames are written into one reused `String`. Numbers are converted with a
+//! two-digit lookup table instead of going through `fmt`, and escape
+//! sequences are put together from static fragments, so once the buffer has
+//! grown to the size of a frame, rendering one allocates nothing.
+
+/// "00" to "99", for converting numbers two digits at a time
+const DIGIT_PAIRS: &[u8; 200] = b"\
+    0001020304050607080910111213141516171819\
+    2021222324252627282930313233343536373839\
+    4041424344454647484950515253545556575859\
+    6061626364656667686970717273747576777879\
+    8081828384858687888990919293949596979899";
+
+/// Append `n` in decimal
+pub(crate) fn push_decimal(out: &mut String, mut n: u32) {
+    let mut digits = [0u8; 10];
+    let mut start = digits.len();
+    while n >= 100 {
+        let pair = (n % 100) as usize * 2;
+        n /= 100;
+        start -= 2;
+        digits[start..start + 2].copy_from_slice(&DIGIT_PAIRS[pair..pair + 2]);
+    }
+    if n >= 10 {
+        let pair = n as usize * 2;
+        start -= 2;
+        digits[start..start + 2].copy_from_slice(&DIGIT_PAIRS[pair..pair + 2]);
+    } else {
+        start -= 1;
+        digits[start] = b'0' + n as u8;
+    }
+    // SAFETY: only ASCII digits were written to digits[start..]
+    out.push_str(unsafe { std::str::from_utf8_unchecked(&digits[start..]) });
+}
+
+/// Append `CSI n <final_byte>`
+pub(crate) fn push_csi(out: &mut String, n: u32, final_byte: char) {
+    out.push_str("\x1b[");
+    push_decimal(out, n);
+    out.push(final_byte);
+}
+
+/// Append `CSI a ; b <final_byte>`
+pub(crate) fn push_csi2(out: &mut String, a: u32, b: u32, final_byte: char) {
+    out.push_str("\x1b[");
+    push_decimal(out, a);
+    out.push(';');
+    push_decimal(out, b);
+    out.push(final_byte);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_decimal() {
+        for n in [
+            0,
+            7,
+            10,
+            42,
+            99,
+            100,
+            101,
+            999,
+            1000,
+            65535,
+            1234567,
+            u32::MAX,
+        ] {
+            let mut out = String::new();
+            push_decimal(&mut out, n);
+            assert_eq!(out, n.to_string());
+        }
+    }
+
+    #[test]
+    fn test_push_csi() {
+        let mut out = String::new();
+        push_csi(&mut out, 12, 'X');
+        push_csi2(&mut out, 3, 140, 'H');
+        assert_eq!(out, "\x1b[12X\x1b[3;140H");
+    }
+}